@@ -0,0 +1,44 @@
+#![deny(warnings)]
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_halt;
+extern crate stm32c0xx_hal as hal;
+
+use core::fmt::Write;
+
+use hal::analog::adc::SampleTime;
+use hal::prelude::*;
+use hal::serial::Config;
+use hal::stm32;
+use rt::entry;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().expect("cannot take peripherals");
+    let mut rcc = dp.RCC.constrain();
+    let mut delay = dp.TIM3.delay(&mut rcc);
+
+    let gpioa = dp.GPIOA.split(&mut rcc);
+    let mut usart = dp
+        .USART2
+        .usart((gpioa.pa2, gpioa.pa3), Config::default(), &mut rcc)
+        .unwrap();
+
+    // Potentiometer wiper on PA0, ends on VDD and GND. The pin is analog with the
+    // pull resistors disconnected so they don't load the divider.
+    let mut pot = gpioa.pa0.into_analog();
+
+    let mut adc = dp.ADC.constrain(&mut rcc);
+    adc.set_sample_time(SampleTime::T_80);
+    adc.calibrate();
+
+    loop {
+        let mv = nb::block!(adc.read_voltage(&mut pot)).unwrap_or(0);
+        writeln!(usart, "pot: {} mV\r", mv).unwrap();
+        delay.delay(500.millis());
+    }
+}
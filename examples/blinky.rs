@@ -15,7 +15,7 @@ fn main() -> ! {
     let mut rcc = dp.RCC.constrain();
 
     let port_a = dp.GPIOA.split(&mut rcc);
-    let mut led = port_a.pa5.into_push_pull_output();
+    let mut led = port_a.pa5.into_push_pull_output_in_state(PinState::Low);
 
     loop {
         led.toggle().ok();
@@ -8,7 +8,6 @@ extern crate cortex_m_rt as rt;
 extern crate panic_halt;
 extern crate stm32c0xx_hal as hal;
 
-use hal::i2c::Config;
 use hal::prelude::*;
 use hal::rcc;
 use hal::stm32;
@@ -21,10 +20,10 @@ fn main() -> ! {
     let mut delay = dp.TIM3.delay(&mut rcc);
 
     let gpiob = dp.GPIOB.split(&mut rcc);
-    let sda = gpiob.pb9.into_open_drain_output_in_state(PinState::High);
-    let scl = gpiob.pb8.into_open_drain_output_in_state(PinState::High);
+    let sda = gpiob.pb9;
+    let scl = gpiob.pb8;
 
-    let mut i2c = dp.I2C.i2c(sda, scl, Config::new(400.kHz()), &mut rcc);
+    let mut i2c = dp.I2C.i2c(sda, scl, 400.kHz(), &mut rcc);
 
     i2c.write(0x2a, &[0x80, 0xff]).unwrap();
     i2c.write(0x2a, &[0x01, 0x04, 0x00, 0x00]).unwrap();
@@ -12,7 +12,6 @@ use hal::exti::Event;
 use hal::gpio::*;
 use hal::prelude::*;
 use hal::stm32;
-use hal::time::*;
 use hal::timer::*;
 
 #[rtic::app(device = hal::stm32, peripherals = true)]
@@ -37,7 +36,7 @@ mod app {
         let gpioc = ctx.device.GPIOC.split(&mut rcc);
 
         let mut timer = ctx.device.TIM17.timer(&mut rcc);
-        timer.start(Hertz::Hz(3).into_duration());
+        timer.start(3.Hz());
         timer.listen();
 
         let mut exti = ctx.device.EXTI;
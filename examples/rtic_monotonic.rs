@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+#![deny(warnings)]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate rtic;
+extern crate stm32c0xx_hal as hal;
+
+use fugit::ExtU64;
+use hal::gpio::*;
+use hal::prelude::*;
+use hal::stm32;
+use hal::timer::monotonic::MonoTimer;
+
+#[rtic::app(device = hal::stm32, peripherals = true, dispatchers = [USART1])]
+mod app {
+    use super::*;
+
+    #[monotonic(binds = TIM3, default = true)]
+    type Mono = MonoTimer<stm32::TIM3, 10_000>;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {
+        led: PA5<Output<PushPull>>,
+    }
+
+    #[init]
+    fn init(ctx: init::Context) -> (Shared, Local, init::Monotonics) {
+        let mut rcc = ctx.device.RCC.constrain();
+        let gpioa = ctx.device.GPIOA.split(&mut rcc);
+        let mono = ctx.device.TIM3.monotonic(&mut rcc);
+
+        blink::spawn().ok();
+
+        (
+            Shared {},
+            Local {
+                led: gpioa.pa5.into_push_pull_output(),
+            },
+            init::Monotonics(mono),
+        )
+    }
+
+    #[task(local = [led])]
+    fn blink(ctx: blink::Context) {
+        ctx.local.led.toggle().ok();
+        blink::spawn_after(500u64.millis()).ok();
+    }
+
+    #[idle]
+    fn idle(_: idle::Context) -> ! {
+        loop {
+            cortex_m::asm::wfi();
+        }
+    }
+}
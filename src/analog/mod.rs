@@ -1 +1,5 @@
+//! Analog peripherals
+//!
+//! The ADC is the only analog peripheral of the C0, there is no DAC or comparator.
+
 pub mod adc;
@@ -13,12 +13,24 @@
 //! let result = crc.result();
 //! assert!(result == 0x78cb);
 //! ```
+//!
+//! The standard CRC-32 (as used by zlib) reflects input and output and inverts the result:
+//! ```
+//! let mut crc = crc
+//!     .input_bit_reversal(Some(crc::BitReversal::ByByte))
+//!     .output_bit_reversal(true)
+//!     .freeze();
+//!
+//! crc.feed(b"123456789");
+//! assert!(crc.result() ^ 0xffff_ffff == 0xcbf4_3926);
+//! ```
 
 #![deny(missing_docs)]
 
 use crate::rcc::{Enable, Rcc, Reset};
 use crate::stm32::CRC;
 use core::hash::Hasher;
+use core::ptr;
 
 /// Extension trait to constrain the CRC peripheral.
 pub trait CrcExt {
@@ -165,12 +177,26 @@ impl Crc {
     }
 
     /// Feed the CRC with data
+    ///
+    /// Whole words are written to the 32-bit data register, a tail that is not a multiple
+    /// of four bytes is fed with 8-bit writes. Without input reversal or with reversal by
+    /// byte the result is the same as feeding the slice byte by byte. Reversal by half
+    /// word or word swaps bits across the whole 16/32-bit write, but not within the 8-bit
+    /// tail, so then the result depends on how the data is split into `feed` calls.
     #[inline]
     pub fn feed(&mut self, data: &[u8]) {
         let crc = unsafe { &(*CRC::ptr()) };
-        for byte in data {
+        let mut words = data.chunks_exact(4);
+        for word in &mut words {
+            let word = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
             unsafe {
-                crc.dr().write(|w| w.bits(*byte as _));
+                crc.dr().write(|w| w.bits(word));
+            }
+        }
+        for byte in words.remainder() {
+            // NOTE(write_volatile) 8-bit access only feeds one byte to the unit
+            unsafe {
+                ptr::write_volatile(crc.dr() as *const _ as *mut u8, *byte);
             }
         }
     }
@@ -194,6 +220,26 @@ impl Crc {
 
         crc.dr().read().bits()
     }
+
+    /// Reads the independent data register.
+    ///
+    /// The register is a general purpose 32-bit scratch word, it is not touched by the CRC
+    /// calculation or by `reset`, so it can be used at any time. Note that it does not survive
+    /// [`CrcExt::constrain`], which resets the whole peripheral.
+    #[inline]
+    pub fn read_idr(&self) -> u32 {
+        let crc = unsafe { &(*CRC::ptr()) };
+
+        crc.idr().read().bits()
+    }
+
+    /// Writes the independent data register, see [`Crc::read_idr`].
+    #[inline]
+    pub fn write_idr(&mut self, value: u32) {
+        let crc = unsafe { &(*CRC::ptr()) };
+
+        crc.idr().write(|w| unsafe { w.bits(value) });
+    }
 }
 
 impl Hasher for Crc {
@@ -0,0 +1,44 @@
+//! Direct Memory Access
+//!
+//! There is no DMA driver yet, the request lines below can be used to route peripheral
+//! requests through the DMAMUX when programming the DMA channels with the PAC.
+//!
+//! An async (`embedded-hal-async`) SPI bus depends on DMA channels signalling transfer
+//! completion, so it will build on the channel driver once it exists. The async serial
+//! in `serial::asynch` (`async` feature) only needs the USART interrupts.
+
+/// DMAMUX request line (`dmareq_id` value of the `DMAMUX_CxCR` registers)
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Request {
+    Generator0 = 1,
+    Generator1 = 2,
+    Generator2 = 3,
+    Generator3 = 4,
+    ADC1 = 5,
+    I2C1_RX = 10,
+    I2C1_TX = 11,
+    SPI1_RX = 16,
+    SPI1_TX = 17,
+    TIM1_CH1 = 20,
+    TIM1_CH2 = 21,
+    TIM1_CH3 = 22,
+    TIM1_CH4 = 23,
+    TIM1_TRIG_COM = 24,
+    TIM1_UP = 25,
+    TIM3_CH1 = 32,
+    TIM3_CH2 = 33,
+    TIM3_CH3 = 34,
+    TIM3_CH4 = 35,
+    TIM3_TRIG = 36,
+    TIM3_UP = 37,
+    TIM16_CH1 = 44,
+    TIM16_COM = 45,
+    TIM16_UP = 46,
+    TIM17_CH1 = 47,
+    TIM17_COM = 48,
+    TIM17_UP = 49,
+    USART1_RX = 50,
+    USART1_TX = 51,
+    USART2_RX = 52,
+    USART2_TX = 53,
+}
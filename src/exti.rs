@@ -58,6 +58,8 @@ pub trait ExtiExt {
     fn listen(&self, ev: Event, edge: SignalEdge);
     fn unlisten(&self, ev: Event);
     fn is_pending(&self, ev: Event, edge: SignalEdge) -> bool;
+    /// Returns the edge that is pending on a line, `All` if both are
+    fn which_edge_pending(&self, ev: Event) -> Option<SignalEdge>;
     fn unpend(&self, ev: Event);
 }
 
@@ -97,6 +99,8 @@ impl ExtiExt for EXTI {
         let mask = !(1 << line);
         self.imr1()
             .modify(|r, w| unsafe { w.bits(r.bits() & mask) });
+        self.emr1()
+            .modify(|r, w| unsafe { w.bits(r.bits() & mask) });
         if line <= TRIGGER_MAX {
             self.rtsr1()
                 .modify(|r, w| unsafe { w.bits(r.bits() & mask) });
@@ -120,6 +124,22 @@ impl ExtiExt for EXTI {
         }
     }
 
+    fn which_edge_pending(&self, ev: Event) -> Option<SignalEdge> {
+        let line = ev as u8;
+        if line > TRIGGER_MAX {
+            return None;
+        }
+        let mask = 1 << line;
+        let rising = self.rpr1().read().bits() & mask != 0;
+        let falling = self.fpr1().read().bits() & mask != 0;
+        match (rising, falling) {
+            (true, true) => Some(SignalEdge::All),
+            (true, false) => Some(SignalEdge::Rising),
+            (false, true) => Some(SignalEdge::Falling),
+            (false, false) => None,
+        }
+    }
+
     fn unpend(&self, ev: Event) {
         let line = ev as u8;
         if line <= TRIGGER_MAX {
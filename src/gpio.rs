@@ -53,6 +53,21 @@ pub struct Output<MODE> {
 /// Push pull output (type state)
 pub struct PushPull;
 
+/// Saved configuration and output levels of a GPIO port
+///
+/// Taken with `snapshot` and written back with `restore` of the port module, e.g.
+/// `gpioa::snapshot()`, around a low power mode that reconfigures the pins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortState {
+    moder: u32,
+    otyper: u32,
+    ospeedr: u32,
+    pupdr: u32,
+    odr: u32,
+    afrl: u32,
+    afrh: u32,
+}
+
 /// Fully erased pin
 pub struct Pin<MODE> {
     i: u8,
@@ -125,7 +140,7 @@ impl<MODE> OutputPin for Pin<Output<MODE>> {
 
 impl<MODE> toggleable::Default for Pin<Output<MODE>> {}
 
-impl InputPin for Pin<Output<OpenDrain>> {
+impl<MODE> InputPin for Pin<Output<MODE>> {
     type Error = Infallible;
 
     #[inline(always)]
@@ -153,6 +168,163 @@ impl<MODE> InputPin for Pin<Input<MODE>> {
     }
 }
 
+impl<MODE> hal1::digital::ErrorType for Pin<MODE> {
+    type Error = Infallible;
+}
+
+impl<MODE> hal1::digital::OutputPin for Pin<Output<MODE>> {
+    #[inline(always)]
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        OutputPin::set_low(self)
+    }
+
+    #[inline(always)]
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        OutputPin::set_high(self)
+    }
+}
+
+impl<MODE> hal1::digital::StatefulOutputPin for Pin<Output<MODE>> {
+    #[inline(always)]
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        StatefulOutputPin::is_set_high(self)
+    }
+
+    #[inline(always)]
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        StatefulOutputPin::is_set_low(self)
+    }
+}
+
+impl<MODE> hal1::digital::InputPin for Pin<Output<MODE>> {
+    #[inline(always)]
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        InputPin::is_high(self)
+    }
+
+    #[inline(always)]
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        InputPin::is_low(self)
+    }
+}
+
+impl<MODE> hal1::digital::InputPin for Pin<Input<MODE>> {
+    #[inline(always)]
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        InputPin::is_high(self)
+    }
+
+    #[inline(always)]
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        InputPin::is_low(self)
+    }
+}
+
+/// Pin with a locked configuration
+///
+/// Returned by the pins' `lock` method. Mode, output type, speed, pull and alternate
+/// function stay frozen until the next reset, so the wrapper only gives access to the
+/// input and output levels.
+pub struct Locked<PIN> {
+    pin: PIN,
+}
+
+impl<PIN: OutputPin> OutputPin for Locked<PIN> {
+    type Error = PIN::Error;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_high()
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_low()
+    }
+}
+
+impl<PIN: StatefulOutputPin> StatefulOutputPin for Locked<PIN> {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        self.pin.is_set_high()
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        self.pin.is_set_low()
+    }
+}
+
+impl<PIN: StatefulOutputPin> toggleable::Default for Locked<PIN> {}
+
+impl<PIN: InputPin> InputPin for Locked<PIN> {
+    type Error = PIN::Error;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        self.pin.is_high()
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.pin.is_low()
+    }
+}
+
+/// Software debounced input
+///
+/// Call `update` periodically, e.g. from a timer interrupt. The pin has to read the
+/// same level for `count` consecutive updates before the state changes.
+pub struct DebouncedInput<PIN> {
+    pin: PIN,
+    active: PinState,
+    count: u8,
+    ticks: u8,
+    pressed: bool,
+    just_pressed: bool,
+}
+
+impl<PIN: InputPin> DebouncedInput<PIN> {
+    /// `active` is the level of a pressed button, `PinState::Low` for a button to ground
+    /// with a pull-up
+    pub fn new(pin: PIN, active: PinState, count: u8) -> Self {
+        DebouncedInput {
+            pin,
+            active,
+            count,
+            ticks: 0,
+            pressed: false,
+            just_pressed: false,
+        }
+    }
+
+    /// Samples the pin, returns the debounced state
+    pub fn update(&mut self) -> bool {
+        let high = self.pin.is_high().unwrap_or(false);
+        let sample = high == (self.active == PinState::High);
+        if sample == self.pressed {
+            self.ticks = 0;
+        } else {
+            self.ticks = self.ticks.saturating_add(1);
+            if self.ticks >= self.count {
+                self.ticks = 0;
+                self.pressed = sample;
+                self.just_pressed |= sample;
+            }
+        }
+        self.pressed
+    }
+
+    /// Returns the debounced state
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    /// Returns true once after each debounced press
+    pub fn just_pressed(&mut self) -> bool {
+        core::mem::replace(&mut self.just_pressed, false)
+    }
+
+    /// Releases the pin
+    pub fn release(self) -> PIN {
+        self.pin
+    }
+}
+
 /// GPIO Pin speed selection
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Speed {
@@ -162,6 +334,14 @@ pub enum Speed {
     VeryHigh = 3,
 }
 
+/// Internal pull resistor selection
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Pull {
+    None = 0,
+    Up = 1,
+    Down = 2,
+}
+
 /// Trigger edge
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum SignalEdge {
@@ -170,6 +350,28 @@ pub enum SignalEdge {
     All,
 }
 
+/// Trigger edge for inputs that can only detect one edge at a time
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SingleEdge {
+    Rising,
+    Falling,
+}
+
+impl From<SingleEdge> for SignalEdge {
+    fn from(edge: SingleEdge) -> Self {
+        match edge {
+            SingleEdge::Rising => SignalEdge::Rising,
+            SingleEdge::Falling => SignalEdge::Falling,
+        }
+    }
+}
+
+/// Alternate function number
+///
+/// Alternate functions are only assigned through the per-peripheral pin traits
+/// (`SDAPin`, `TxPin`, `PinSck`, `TimerPin`, ...). These are implemented for the
+/// pin and AF pairs of the datasheet table only, so passing a pin that cannot
+/// do that function fails to compile.
 #[allow(dead_code)]
 pub(crate) enum AltFunction {
     AF0 = 0,
@@ -190,6 +392,63 @@ pub(crate) enum AltFunction {
     AF15 = 15,
 }
 
+/// embedded-hal 1.0 digital traits, forwarded to the 0.2 implementations
+macro_rules! digital_hal1 {
+    ($PIN:ident) => {
+        impl<MODE> hal1::digital::ErrorType for $PIN<MODE> {
+            type Error = Infallible;
+        }
+
+        impl<MODE> hal1::digital::OutputPin for $PIN<Output<MODE>> {
+            #[inline(always)]
+            fn set_low(&mut self) -> Result<(), Self::Error> {
+                OutputPin::set_low(self)
+            }
+
+            #[inline(always)]
+            fn set_high(&mut self) -> Result<(), Self::Error> {
+                OutputPin::set_high(self)
+            }
+        }
+
+        impl<MODE> hal1::digital::StatefulOutputPin for $PIN<Output<MODE>> {
+            #[inline(always)]
+            fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+                StatefulOutputPin::is_set_high(self)
+            }
+
+            #[inline(always)]
+            fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+                StatefulOutputPin::is_set_low(self)
+            }
+        }
+
+        impl<MODE> hal1::digital::InputPin for $PIN<Output<MODE>> {
+            #[inline(always)]
+            fn is_high(&mut self) -> Result<bool, Self::Error> {
+                InputPin::is_high(self)
+            }
+
+            #[inline(always)]
+            fn is_low(&mut self) -> Result<bool, Self::Error> {
+                InputPin::is_low(self)
+            }
+        }
+
+        impl<MODE> hal1::digital::InputPin for $PIN<Input<MODE>> {
+            #[inline(always)]
+            fn is_high(&mut self) -> Result<bool, Self::Error> {
+                InputPin::is_high(self)
+            }
+
+            #[inline(always)]
+            fn is_low(&mut self) -> Result<bool, Self::Error> {
+                InputPin::is_low(self)
+            }
+        }
+    };
+}
+
 macro_rules! gpio {
     ($GPIOX:ident, $gpiox:ident, $PXx:ident, $Pxn:expr, [
         $($PXi:ident: ($pxi:ident, $i:expr),)+
@@ -225,12 +484,58 @@ macro_rules! gpio {
                 }
             }
 
+            /// Saves the configuration and output levels of all pins of the port
+            pub fn snapshot() -> PortState {
+                // NOTE(unsafe) atomic reads with no side effects
+                let gpio = unsafe { &(*$GPIOX::ptr()) };
+                PortState {
+                    moder: gpio.moder().read().bits(),
+                    otyper: gpio.otyper().read().bits(),
+                    ospeedr: gpio.ospeedr().read().bits(),
+                    pupdr: gpio.pupdr().read().bits(),
+                    odr: gpio.odr().read().bits(),
+                    afrl: gpio.afrl().read().bits(),
+                    afrh: gpio.afrh().read().bits(),
+                }
+            }
+
+            /// Restores a configuration saved with `snapshot`
+            ///
+            /// The output levels are written first and the modes last, so outputs come back
+            /// driving their saved level. The pin types are not touched, so only restore a
+            /// snapshot of the same port taken while the pins had their current types.
+            pub fn restore(state: &PortState) {
+                let gpio = unsafe { &(*$GPIOX::ptr()) };
+                unsafe {
+                    gpio.odr().write(|w| w.bits(state.odr));
+                    gpio.otyper().write(|w| w.bits(state.otyper));
+                    gpio.ospeedr().write(|w| w.bits(state.ospeedr));
+                    gpio.pupdr().write(|w| w.bits(state.pupdr));
+                    gpio.afrl().write(|w| w.bits(state.afrl));
+                    gpio.afrh().write(|w| w.bits(state.afrh));
+                    gpio.moder().write(|w| w.bits(state.moder));
+                }
+            }
+
             /// Partially erased pin
             pub struct $PXx<MODE> {
                 i: u8,
                 _mode: PhantomData<MODE>,
             }
 
+            impl<MODE> $PXx<MODE> {
+                /// Set pin speed
+                pub fn set_speed(self, speed: Speed) -> Self {
+                    let offset = 2 * self.i;
+                    unsafe {
+                        let _ = &(*$GPIOX::ptr()).ospeedr().modify(|r, w| {
+                            w.bits((r.bits() & !(0b11 << offset)) | ((speed as u32) << offset))
+                        });
+                    };
+                    self
+                }
+            }
+
             impl<MODE> OutputPin for $PXx<Output<MODE>> {
                 type Error = Infallible;
 
@@ -263,6 +568,24 @@ macro_rules! gpio {
             impl<MODE> toggleable::Default for $PXx<Output<MODE>> {
             }
 
+            impl<MODE> $PXx<Output<MODE>> {
+                /// Toggles a group of pins of this port at once
+                ///
+                /// `toggle` already reads ODR and writes BSRR, this does the same for all
+                /// `pins` with a single read and a single BSRR store, so they switch together.
+                pub fn toggle_n(pins: &mut [Self]) {
+                    let mask = pins.iter().fold(0u32, |mask, pin| mask | 1 << pin.i);
+                    // NOTE(unsafe) atomic read with no side effects
+                    let odr = unsafe { (*$GPIOX::ptr()).odr().read().bits() };
+                    let set = !odr & mask;
+                    let reset = odr & mask;
+                    // NOTE(unsafe) atomic write to a stateless register
+                    unsafe { (*$GPIOX::ptr()).bsrr().write(|w| w.bits(set | reset << 16)) };
+                }
+            }
+
+            digital_hal1!($PXx);
+
             impl<MODE> InputPin for $PXx<Output<MODE>> {
                 type Error = Infallible;
 
@@ -380,6 +703,9 @@ macro_rules! gpio {
                     }
 
                     /// Configures the pin to operate as an analog pin
+                    ///
+                    /// The pull resistors are disabled and the digital input is
+                    /// disconnected, so the pin doesn't load an ADC input.
                     pub fn into_analog(self) -> $PXi<Analog> {
                         let offset = 2 * $i;
                         unsafe {
@@ -402,6 +728,29 @@ macro_rules! gpio {
                         self.into_open_drain_output()
                     }
 
+                    /// Configures the pin to operate as an open drain output
+                    /// pin with the internal `pull` resistor enabled
+                    ///
+                    /// Output level, pull and output type are set before the pin
+                    /// is switched to output mode, so the line doesn't glitch.
+                    pub fn into_open_drain_output_with_pull(mut self, initial_state: PinState, pull: Pull) -> $PXi<Output<OpenDrain>> {
+                        self.internal_set_state(initial_state);
+                        let offset = 2 * $i;
+                        unsafe {
+                            let gpio = &(*$GPIOX::ptr());
+                            gpio.pupdr().modify(|r, w| {
+                                w.bits((r.bits() & !(0b11 << offset)) | ((pull as u32) << offset))
+                            });
+                            gpio.otyper().modify(|r, w| {
+                                w.bits(r.bits() | (0b1 << $i))
+                            });
+                            gpio.moder().modify(|r, w| {
+                                w.bits((r.bits() & !(0b11 << offset)) | (0b01 << offset))
+                            })
+                        };
+                        $PXi { _mode: PhantomData }
+                    }
+
                     /// Configures the pin to operate as an open drain output pin
                     pub fn into_open_drain_output(self) -> $PXi<Output<OpenDrain>> {
                         let offset = 2 * $i;
@@ -423,6 +772,9 @@ macro_rules! gpio {
                     /// Configures the pin to operate as a push pull output pin
                     /// with `initial_state` specifying whether the pin should
                     /// initially be high or low
+                    ///
+                    /// The level is written through BSRR before MODER switches the
+                    /// pin to output, so it never drives a stale ODR value.
                     pub fn into_push_pull_output_in_state(mut self, initial_state: PinState) -> $PXi<Output<PushPull>> {
                         self.internal_set_state(initial_state);
                         self.into_push_pull_output()
@@ -479,7 +831,20 @@ macro_rules! gpio {
                         $PXi { _mode: PhantomData }
                     }
 
-                    /// Set pin speed
+                    /// Configures the pin as a wakeup source for Stop mode
+                    ///
+                    /// Same as `listen`, the line is unmasked in IMR1 so an edge sets the
+                    /// pending flag and wakes a `WFI`, as long as the EXTI interrupt is enabled
+                    /// in the NVIC. The line is additionally unmasked in EMR1, so the edge also
+                    /// wakes a `WFE` without any interrupt being enabled or handled.
+                    pub fn listen_wakeup(self, edge: SignalEdge, exti: &mut EXTI) -> $PXi<Input<Floating>> {
+                        let pin = self.listen(edge, exti);
+                        exti.emr1().modify(|r, w| unsafe { w.bits(r.bits() | 1 << $i) });
+                        pin
+                    }
+
+                    /// Set pin speed, available in every mode and chainable. It only affects
+                    /// the pin while it is driven as an output or alternate function.
                     pub fn set_speed(self, speed: Speed) -> Self {
                         let offset = 2 * $i;
                         unsafe {
@@ -490,6 +855,42 @@ macro_rules! gpio {
                         self
                     }
 
+                    /// Reads back the alternate function number, `None` if the pin is
+                    /// not in alternate function mode
+                    pub fn alternate_function(&self) -> Option<u8> {
+                        let gpio = unsafe { &(*$GPIOX::ptr()) };
+                        if (gpio.moder().read().bits() >> (2 * $i)) & 0b11 != 0b10 {
+                            return None;
+                        }
+                        let offset = 4 * $i;
+                        let af = if offset < 32 {
+                            gpio.afrl().read().bits() >> offset
+                        } else {
+                            gpio.afrh().read().bits() >> (offset - 32)
+                        };
+                        Some((af & 0b1111) as u8)
+                    }
+
+                    #[allow(dead_code)]
+                    pub(crate) fn set_open_drain(&self) {
+                        unsafe {
+                            let gpio = &(*$GPIOX::ptr());
+                            gpio.otyper().modify(|r, w| {
+                                w.bits(r.bits() | (0b1 << $i))
+                            });
+                        }
+                    }
+
+                    #[allow(dead_code)]
+                    pub(crate) fn set_push_pull(&self) {
+                        unsafe {
+                            let gpio = &(*$GPIOX::ptr());
+                            gpio.otyper().modify(|r, w| {
+                                w.bits(r.bits() & !(0b1 << $i))
+                            });
+                        }
+                    }
+
                     #[allow(dead_code)]
                     pub(crate) fn set_alt_mode(&self, mode: AltFunction) {
                         let mode = mode as u32;
@@ -513,6 +914,31 @@ macro_rules! gpio {
                         }
                     }
 
+                    /// Locks the pin configuration until the next reset
+                    ///
+                    /// The lock key sequence freezes the whole LCKR of the port, so only
+                    /// the first `lock` on each port succeeds, a later one gives the pin
+                    /// back as the error.
+                    pub fn lock(self) -> Result<Locked<Self>, Self> {
+                        const LCKK: u32 = 1 << 16;
+                        let gpio = unsafe { &(*$GPIOX::ptr()) };
+                        let locked = cortex_m::interrupt::free(|_| {
+                            let pins = gpio.lckr().read().bits() & 0xffff | (1 << $i);
+                            unsafe {
+                                gpio.lckr().write(|w| w.bits(LCKK | pins));
+                                gpio.lckr().write(|w| w.bits(pins));
+                                gpio.lckr().write(|w| w.bits(LCKK | pins));
+                            }
+                            let _ = gpio.lckr().read();
+                            gpio.lckr().read().bits()
+                        });
+                        if locked & LCKK != 0 && locked & (1 << $i) != 0 {
+                            Ok(Locked { pin: self })
+                        } else {
+                            Err(self)
+                        }
+                    }
+
                     fn internal_set_state(&mut self, state: PinState) {
                         match state {
                             PinState::High => {
@@ -567,6 +993,10 @@ macro_rules! gpio {
                 impl<MODE> toggleable::Default for $PXi<Output<MODE>> {
                 }
 
+                digital_hal1!($PXi);
+
+                /// Reads the input data register, so an open drain output reports the
+                /// actual line level rather than the level it drives
                 impl<MODE> InputPin for $PXi<Output<MODE>> {
                     type Error = Infallible;
 
@@ -590,6 +1020,29 @@ macro_rules! gpio {
                     pub fn downgrade(self) -> $PXx<Input<MODE>> {
                         $PXx { i: $i, _mode: self._mode }
                     }
+
+                    /// Returns true if the EXTI line of this pin has a pending edge
+                    ///
+                    /// The line is shared with the same pin number of the other ports,
+                    /// only the port selected by `listen` can trigger it.
+                    pub fn is_interrupt_pending(&self) -> bool {
+                        // NOTE(unsafe) atomic read with no side effects
+                        let exti = unsafe { &(*EXTI::ptr()) };
+                        (exti.rpr1().read().bits() | exti.fpr1().read().bits()) & (1 << $i) != 0
+                    }
+
+                    /// Clears the pending edge of the EXTI line of this pin
+                    pub fn clear_interrupt_pending(&mut self) {
+                        // NOTE(unsafe) atomic write to a write-one-to-clear register
+                        let exti = unsafe { &(*EXTI::ptr()) };
+                        exti.rpr1().write(|w| unsafe { w.bits(1 << $i) });
+                        exti.fpr1().write(|w| unsafe { w.bits(1 << $i) });
+                    }
+
+                    /// Stops the EXTI line of this pin from triggering interrupts
+                    pub fn disable_interrupt(&mut self, exti: &mut EXTI) {
+                        exti.unlisten(Event::from_code($i));
+                    }
                 }
 
                 impl<MODE> InputPin for $PXi<Input<MODE>> {
@@ -614,6 +1067,69 @@ macro_rules! gpio {
                 }
             }
 
+            /// Several input pins of this port read as one value
+            ///
+            /// The pins are added with [`InputBus::pin`], the first one becomes bit 0 of the
+            /// value returned by [`InputBus::read`], the next one bit 1 and so on. Pins don't
+            /// need to be adjacent, if they are the value is taken with a single mask and shift.
+            pub struct InputBus {
+                pins: [u8; 16],
+                len: u8,
+                mask: u16,
+                contiguous: bool,
+            }
+
+            impl InputBus {
+                /// Creates an empty bus
+                pub fn new() -> Self {
+                    InputBus {
+                        pins: [0; 16],
+                        len: 0,
+                        mask: 0,
+                        contiguous: true,
+                    }
+                }
+
+                /// Adds a pin as the next (more significant) bit of the bus
+                pub fn pin<MODE>(mut self, pin: $PXx<Input<MODE>>) -> Self {
+                    let i = pin.get_id();
+                    if self.len > 0 && i != self.pins[self.len as usize - 1] + 1 {
+                        self.contiguous = false;
+                    }
+                    self.pins[self.len as usize] = i;
+                    self.len += 1;
+                    self.mask |= 1 << i;
+                    self
+                }
+
+                /// Returns the number of pins on the bus
+                pub fn width(&self) -> u8 {
+                    self.len
+                }
+
+                /// Reads all pins of the bus from a single IDR access
+                pub fn read(&self) -> u16 {
+                    // NOTE(unsafe) atomic read with no side effects
+                    let idr = unsafe { (*$GPIOX::ptr()).idr().read().bits() } as u16;
+                    if self.len == 0 {
+                        0
+                    } else if self.contiguous {
+                        (idr & self.mask) >> self.pins[0]
+                    } else {
+                        self.pins[..self.len as usize]
+                            .iter()
+                            .enumerate()
+                            .fold(0, |value, (bit, &i)| value | (((idr >> i) & 1) << bit))
+                    }
+                }
+            }
+
+            impl Default for InputBus {
+                fn default() -> Self {
+                    Self::new()
+                }
+            }
+
             // impl<MODE> $PXx<Output<MODE>> {
             //     /// Erases the port number from the type
             //     ///
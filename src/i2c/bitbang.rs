@@ -0,0 +1,221 @@
+//! Software I2C master on GPIO pins
+use crate::i2c::Error;
+use crate::time::Hertz;
+use core::convert::Infallible;
+use hal::blocking::delay::DelayUs;
+use hal::blocking::i2c::{Read, Write, WriteRead};
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// Longest clock stretching accepted from a slave, in microseconds
+const STRETCH_TIMEOUT_US: u32 = 25_000;
+
+/// Bit banged I2C master
+///
+/// SDA and SCL must be open drain outputs with pull-ups on the bus, so the actual
+/// line level can be read back for clock stretching and arbitration. Only 7 bit
+/// addressing is supported.
+pub struct BitBangI2c<SDA, SCL, DELAY> {
+    sda: SDA,
+    scl: SCL,
+    delay: DELAY,
+    half_period: u32,
+}
+
+impl<SDA, SCL, DELAY> BitBangI2c<SDA, SCL, DELAY>
+where
+    SDA: OutputPin<Error = Infallible> + InputPin<Error = Infallible>,
+    SCL: OutputPin<Error = Infallible> + InputPin<Error = Infallible>,
+    DELAY: DelayUs<u32>,
+{
+    /// Creates a software I2C master clocking the bus at roughly `freq`
+    pub fn new(mut sda: SDA, mut scl: SCL, delay: DELAY, freq: Hertz) -> Self {
+        let _ = sda.set_high();
+        let _ = scl.set_high();
+        let half_period = (500_000 / freq.raw().max(1)).max(1);
+        BitBangI2c {
+            sda,
+            scl,
+            delay,
+            half_period,
+        }
+    }
+
+    /// Releases the pins and the delay provider
+    pub fn release(self) -> (SDA, SCL, DELAY) {
+        (self.sda, self.scl, self.delay)
+    }
+
+    fn wait(&mut self) {
+        self.delay.delay_us(self.half_period);
+    }
+
+    /// Releases SCL and waits until a stretching slave lets it go high
+    ///
+    /// Gives up with `Error::Timeout` if SCL is still held low after
+    /// `STRETCH_TIMEOUT_US`, e.g. by a hung slave or a short on the bus.
+    fn scl_release(&mut self) -> Result<(), Error> {
+        let _ = self.scl.set_high();
+        let mut budget = STRETCH_TIMEOUT_US;
+        while self.scl.is_low().unwrap_or(false) {
+            if budget == 0 {
+                return Err(Error::Timeout);
+            }
+            budget -= 1;
+            self.delay.delay_us(1);
+        }
+        Ok(())
+    }
+
+    fn sda_is_high(&self) -> bool {
+        self.sda.is_high().unwrap_or(false)
+    }
+
+    /// Start or repeated start condition
+    fn start(&mut self) -> Result<(), Error> {
+        let _ = self.sda.set_high();
+        self.wait();
+        self.scl_release()?;
+        if !self.sda_is_high() {
+            return Err(Error::ArbitrationLost);
+        }
+        self.wait();
+        let _ = self.sda.set_low();
+        self.wait();
+        let _ = self.scl.set_low();
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), Error> {
+        let _ = self.sda.set_low();
+        self.wait();
+        self.scl_release()?;
+        self.wait();
+        let _ = self.sda.set_high();
+        self.wait();
+        Ok(())
+    }
+
+    fn write_bit(&mut self, bit: bool) -> Result<(), Error> {
+        if bit {
+            let _ = self.sda.set_high();
+        } else {
+            let _ = self.sda.set_low();
+        }
+        self.wait();
+        self.scl_release()?;
+        if bit && !self.sda_is_high() {
+            return Err(Error::ArbitrationLost);
+        }
+        self.wait();
+        let _ = self.scl.set_low();
+        Ok(())
+    }
+
+    fn read_bit(&mut self) -> Result<bool, Error> {
+        let _ = self.sda.set_high();
+        self.wait();
+        self.scl_release()?;
+        let bit = self.sda_is_high();
+        self.wait();
+        let _ = self.scl.set_low();
+        Ok(bit)
+    }
+
+    fn write_byte(&mut self, byte: u8) -> Result<(), Error> {
+        for i in (0..8).rev() {
+            self.write_bit(byte & (1 << i) != 0)?;
+        }
+        if self.read_bit()? {
+            return Err(Error::Nack);
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self, ack: bool) -> Result<u8, Error> {
+        let mut byte = 0;
+        for _ in 0..8 {
+            byte = (byte << 1) | self.read_bit()? as u8;
+        }
+        self.write_bit(!ack)?;
+        Ok(byte)
+    }
+
+    fn write_bytes(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
+        self.start()?;
+        self.write_byte(addr << 1)?;
+        for byte in bytes {
+            self.write_byte(*byte)?;
+        }
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        self.start()?;
+        self.write_byte((addr << 1) | 1)?;
+        let len = buffer.len();
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.read_byte(i + 1 < len)?;
+        }
+        Ok(())
+    }
+
+    /// Ends the transaction, leaving the bus alone if another master owns it or
+    /// SCL is stuck low
+    fn finish(&mut self, res: Result<(), Error>) -> Result<(), Error> {
+        match res {
+            Err(Error::ArbitrationLost) | Err(Error::Timeout) => {
+                let _ = self.sda.set_high();
+                let _ = self.scl.set_high();
+                res
+            }
+            _ => {
+                let stop = self.stop();
+                res.and(stop)
+            }
+        }
+    }
+}
+
+impl<SDA, SCL, DELAY> Write for BitBangI2c<SDA, SCL, DELAY>
+where
+    SDA: OutputPin<Error = Infallible> + InputPin<Error = Infallible>,
+    SCL: OutputPin<Error = Infallible> + InputPin<Error = Infallible>,
+    DELAY: DelayUs<u32>,
+{
+    type Error = Error;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        let res = self.write_bytes(addr, bytes);
+        self.finish(res)
+    }
+}
+
+impl<SDA, SCL, DELAY> Read for BitBangI2c<SDA, SCL, DELAY>
+where
+    SDA: OutputPin<Error = Infallible> + InputPin<Error = Infallible>,
+    SCL: OutputPin<Error = Infallible> + InputPin<Error = Infallible>,
+    DELAY: DelayUs<u32>,
+{
+    type Error = Error;
+
+    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        let res = self.read_bytes(addr, buffer);
+        self.finish(res)
+    }
+}
+
+impl<SDA, SCL, DELAY> WriteRead for BitBangI2c<SDA, SCL, DELAY>
+where
+    SDA: OutputPin<Error = Infallible> + InputPin<Error = Infallible>,
+    SCL: OutputPin<Error = Infallible> + InputPin<Error = Infallible>,
+    DELAY: DelayUs<u32>,
+{
+    type Error = Error;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        let res = self
+            .write_bytes(addr, bytes)
+            .and_then(|_| self.read_bytes(addr, buffer));
+        self.finish(res)
+    }
+}
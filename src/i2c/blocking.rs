@@ -1,7 +1,9 @@
 //! I2C
 use crate::gpio::*;
 use crate::i2c::config::Config;
-use crate::i2c::{self, Error, I2c, I2cDirection, I2cExt, SCLPin, SDAPin};
+use crate::i2c::{
+    self, ByteOrder, Error, I2c, I2cDirection, I2cExt, SCLPin, SDAPin, SmbusMode,
+};
 use crate::rcc::*;
 use crate::stm32::I2C;
 use hal::blocking::i2c::{Read, Write, WriteRead};
@@ -30,6 +32,12 @@ pub trait I2cSlave {
     /// If the master wants more data than bytes.len()  the master will run into a timeout, This function will return Ok(())
     /// If the master wants less data than bytes.len(), the function will return  IncorrectFrameSize(bytes.len() + 1)
     fn slave_write(&mut self, bytes: &[u8]) -> Result<(), Error>;
+
+    /// Set and enable the (7 bit) adress. To keep the interface generic, only slave address 1 can be set
+    fn set_address(&mut self, address: u16);
+
+    /// Disable both own addresses, the peripheral no longer acknowledges as a slave
+    fn disable_slave(&mut self);
 }
 
 /// Sequence to flush the TXDR register. This resets the TXIS and TXE flags
@@ -60,8 +68,19 @@ macro_rules! flush_rxdr {
 /// In case of hard errors the error will be returned, also forcing the caller of this function to return
 /// In all other case the macro will return without a result
 macro_rules! busy_wait {
-    ($i2c:expr, $flag:ident, $variant:ident, $idx:ident, $buflen:ident) => {
+    ($i2c:expr, $flag:ident, $variant:ident, $idx:ident, $buflen:ident, $timeout:expr) => {
+        let mut polls: u32 = 0;
         loop {
+            if $timeout != 0 {
+                if polls == $timeout {
+                    // Clearing PE resets the state machine and releases the lines
+                    $i2c.cr1().modify(|_, w| w.pe().clear_bit());
+                    while $i2c.cr1().read().pe().bit_is_set() {}
+                    $i2c.cr1().modify(|_, w| w.pe().set_bit());
+                    return Err(Error::Timeout);
+                }
+                polls += 1;
+            }
             let isr = $i2c.isr().read();
 
             if isr.$flag().$variant() {
@@ -112,29 +131,37 @@ macro_rules! busy_wait {
 
 macro_rules! i2c {
     ($I2CX:ident, $i2cx:ident,
-        sda: [ $(($PSDA:ty, $AFSDA:expr),)+ ],
-        scl: [ $(($PSCL:ty, $AFSCL:expr),)+ ],
+        sda: [ $(($PSDA:ident, $AFSDA:expr),)+ ],
+        scl: [ $(($PSCL:ident, $AFSCL:expr),)+ ],
     ) => {
         $(
-            impl SDAPin<$I2CX> for $PSDA {
+            impl<MODE> SDAPin<$I2CX> for $PSDA<MODE> {
+                type Released = $PSDA<DefaultMode>;
+
                 fn setup(&self) {
+                    self.set_open_drain();
                     self.set_alt_mode($AFSDA)
                 }
 
-                fn release(self) -> Self {
-                    self.into_open_drain_output()
+                fn release(self) -> Self::Released {
+                    self.set_push_pull();
+                    self.into_analog()
                 }
             }
         )+
 
         $(
-            impl SCLPin<$I2CX> for $PSCL {
+            impl<MODE> SCLPin<$I2CX> for $PSCL<MODE> {
+                type Released = $PSCL<DefaultMode>;
+
                 fn setup(&self) {
+                    self.set_open_drain();
                     self.set_alt_mode($AFSCL)
                 }
 
-                fn release(self) -> Self {
-                    self.into_open_drain_output()
+                fn release(self) -> Self::Released {
+                    self.set_push_pull();
+                    self.into_analog()
                 }
             }
         )+
@@ -175,16 +202,24 @@ macro_rules! i2c {
                 let timing_bits = config.timing_bits(rcc.clocks.apb_clk);
                 i2c.timingr().write(|w| unsafe { w.bits(timing_bits) });
 
-                // Enable the I2C processing
+                // The filters can only be changed while PE is cleared
                 i2c.cr1().modify(|_, w| unsafe {
-                    w.pe()
-                        .set_bit()
-                        .dnf()
-                        .bits(config.digital_filter)
+                    w.dnf()
+                        .bits(config.digital_filter_bits())
                         .anfoff()
                         .bit(!config.analog_filter)
                 });
 
+                i2c.cr1().modify(|_, w| {
+                    w.smbhen()
+                        .bit(config.smbus == Some(SmbusMode::Host))
+                        .smbden()
+                        .bit(config.smbus == Some(SmbusMode::Device))
+                });
+
+                // Enable the I2C processing
+                i2c.cr1().modify(|_, w| w.pe().set_bit());
+
                 if config.slave_address_1 > 0 {
                     i2c.oar1().write(|w| unsafe {
                         w.oa1().bits(config.slave_address_1)
@@ -209,7 +244,7 @@ macro_rules! i2c {
                 sda.setup();
                 scl.setup();
 
-                I2c { i2c, sda, scl }
+                I2c { i2c, sda, scl, timeout: config.busy_timeout }
             }
 
             pub fn listen(&mut self, ev: i2c::Event) {
@@ -233,7 +268,23 @@ macro_rules! i2c {
                 }
             }
 
-            pub fn release(self) -> ($I2CX, SDA, SCL) {
+            /// Enables the SMBus alert: a host detects a falling edge on SMBA, a device
+            /// drives SMBA low. The SMBA pin itself isn't set up by the driver.
+            pub fn enable_alert(&mut self, enable: bool) {
+                self.i2c.cr1().modify(|_, w| w.alerten().bit(enable));
+            }
+
+            /// Returns true if a host detected an SMBus alert
+            pub fn is_alert_pending(&self) -> bool {
+                self.i2c.isr().read().alert().bit_is_set()
+            }
+
+            /// Clears the SMBus alert flag
+            pub fn clear_alert(&mut self) {
+                self.i2c.icr().write(|w| w.alertcf().set_bit());
+            }
+
+            pub fn release(self) -> ($I2CX, SDA::Released, SCL::Released) {
                 (self.i2c, self.sda.release(), self.scl.release())
             }
         }
@@ -249,8 +300,12 @@ macro_rules! i2c {
             ) -> Result<(), Self::Error> {
                 let sndlen = snd_buffer.len();
                 let rcvlen = rcv_buffer.len();
-                assert!(sndlen < 256 && sndlen > 0);
-                assert!(rcvlen < 256 && rcvlen > 0);
+                if sndlen == 0 || sndlen > 255 {
+                    return Err(Error::IncorrectFrameSize(sndlen));
+                }
+                if rcvlen == 0 || rcvlen > 255 {
+                    return Err(Error::IncorrectFrameSize(rcvlen));
+                }
 
                 // Wait for any previous address sequence to end automatically.
                 // This could be up to 50% of a bus cycle (ie. up to 0.5/freq)
@@ -283,14 +338,14 @@ macro_rules! i2c {
                 // (START has been ACKed or last byte went through)
                 // macro will return false when the tc bit is set
                 for byte in snd_buffer {
-                    busy_wait!(self.i2c, txis, bit_is_set, idx, sndlen);
+                    busy_wait!(self.i2c, txis, bit_is_set, idx, sndlen, self.timeout);
                     // Put byte on the wire
                     self.i2c.txdr().write(|w| unsafe { w.txdata().bits(*byte) });
                     idx += 1;
                 }
                 // Wait until the write finishes before beginning to read.
                 let dummy  = 0xFE;
-                busy_wait!(self.i2c, tc, bit_is_set, idx, dummy );
+                busy_wait!(self.i2c, tc, bit_is_set, idx, dummy, self.timeout);
 
                 // reSTART and prepare to receive bytes into `rcv_buffer`
                 self.i2c.cr2().write(|w| unsafe {
@@ -313,7 +368,7 @@ macro_rules! i2c {
                 idx = 0;
                 loop {
                     // Wait until we have received something. Handle all state in busy_wait macro
-                    busy_wait!(self.i2c, rxne, bit_is_set, idx, rcvlen);
+                    busy_wait!(self.i2c, rxne, bit_is_set, idx, rcvlen, self.timeout);
                     if idx < rcvlen {
                         rcv_buffer[idx] = self.i2c.rxdr().read().rxdata().bits();
                         idx +=1;
@@ -327,7 +382,9 @@ macro_rules! i2c {
 
             fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
                 let buflen = bytes.len();
-                assert!(buflen < 256 && buflen > 0);
+                if buflen == 0 || buflen > 255 {
+                    return Err(Error::IncorrectFrameSize(buflen));
+                }
 
                 // Wait for any previous address sequence to end automatically.
                 // This could be up to 50% of a bus cycle (ie. up to 0.5/freq)
@@ -351,7 +408,7 @@ macro_rules! i2c {
                 let mut idx = 0;
                 loop {
                     // Wait until we are allowed to send data, handle all state in busy_wait macro
-                    busy_wait!(self.i2c, txis, bit_is_set, idx, buflen);
+                    busy_wait!(self.i2c, txis, bit_is_set, idx, buflen, self.timeout);
 
                     // Put byte on the wire
                     if idx < buflen {
@@ -367,7 +424,9 @@ macro_rules! i2c {
 
             fn read(&mut self, addr: u8, bytes: &mut [u8]) -> Result<(), Self::Error> {
                 let buflen = bytes.len();
-                assert!(buflen < 256 && buflen > 0);
+                if buflen == 0 || buflen > 255 {
+                    return Err(Error::IncorrectFrameSize(buflen));
+                }
 
                 // Wait for any previous address sequence to end automatically.
                 // This could be up to 50% of a bus cycle (ie. up to 0.5/freq)
@@ -395,7 +454,7 @@ macro_rules! i2c {
                 let mut idx = 0;
                 loop {
                     // Wait until we have received something
-                    busy_wait!(self.i2c, rxne, bit_is_set, idx, buflen);
+                    busy_wait!(self.i2c, rxne, bit_is_set, idx, buflen, self.timeout);
                     if idx < buflen {
                         bytes[idx] = self.i2c.rxdr().read().rxdata().bits();
                         idx +=1;
@@ -404,6 +463,35 @@ macro_rules! i2c {
             }
         }
 
+        impl<SDA, SCL> I2c<$I2CX, SDA, SCL> {
+            /// Writes a 16-bit value to a device with 16-bit register addresses
+            ///
+            /// The register address is always sent most significant byte first, `order`
+            /// only applies to the value.
+            pub fn write_reg16(&mut self, addr: u8, reg: u16, value: u16, order: ByteOrder) -> Result<(), Error> {
+                let value = match order {
+                    ByteOrder::MsbFirst => value.to_be_bytes(),
+                    ByteOrder::LsbFirst => value.to_le_bytes(),
+                };
+                let reg = reg.to_be_bytes();
+                Write::write(self, addr, &[reg[0], reg[1], value[0], value[1]])
+            }
+
+            /// Reads a 16-bit value from a device with 16-bit register addresses, with a
+            /// repeated start between the register address and the value
+            ///
+            /// The register address is always sent most significant byte first, `order`
+            /// only applies to the value.
+            pub fn read_reg16(&mut self, addr: u8, reg: u16, order: ByteOrder) -> Result<u16, Error> {
+                let mut value = [0; 2];
+                WriteRead::write_read(self, addr, &reg.to_be_bytes(), &mut value)?;
+                Ok(match order {
+                    ByteOrder::MsbFirst => u16::from_be_bytes(value),
+                    ByteOrder::LsbFirst => u16::from_le_bytes(value),
+                })
+            }
+        }
+
         impl<SDA, SCL> I2cSlave for I2c<$I2CX, SDA, SCL> {
 
             fn slave_sbc(&mut self, sbc_enabled: bool)  {
@@ -411,6 +499,25 @@ macro_rules! i2c {
                 self.i2c.cr1().modify(|_, w|  w.sbc().bit(sbc_enabled) );
             }
 
+            fn set_address(&mut self, address:u16) {
+                // OA1 can only be changed while OA1EN is cleared
+                self.i2c.oar1().write(|w| unsafe {
+                    w.oa1().bits(address as _)
+                    .oa1en().clear_bit()
+                });
+                // set the 7 bits address
+                self.i2c.oar1().write(|w| unsafe {
+                    w.oa1().bits(address as _)
+                    .oa1mode().clear_bit()
+                    .oa1en().set_bit()
+                });
+            }
+
+            fn disable_slave(&mut self) {
+                self.i2c.oar1().modify(|_, w| w.oa1en().clear_bit());
+                self.i2c.oar2().modify(|_, w| w.oa2en().clear_bit());
+            }
+
             fn slave_addressed(&mut self) -> Result<Option<(u16, I2cDirection)>, Error> {
                 if self.i2c.isr().read().addr().bit_is_set() {
                     let isr = self.i2c.isr().read();
@@ -441,7 +548,9 @@ macro_rules! i2c {
 
             fn slave_write(&mut self, bytes: &[u8]) -> Result<(), Error> {
                 let buflen = bytes.len();
-                assert!(buflen < 256 && buflen > 0);
+                if buflen == 0 || buflen > 255 {
+                    return Err(Error::IncorrectFrameSize(buflen))
+                };
 
                 // Set the nbytes and prepare to send bytes into `buffer`.
                 self.i2c.cr2().modify(|_, w| unsafe {
@@ -456,7 +565,7 @@ macro_rules! i2c {
                 let mut idx = 0;
                 loop {
                     // wait until we are allowed to send the byte. Handle all state in macro
-                    busy_wait!(self.i2c, txis, bit_is_set, idx, buflen);
+                    busy_wait!(self.i2c, txis, bit_is_set, idx, buflen, self.timeout);
 
                     // Put byte on the wire
                     if idx < buflen {
@@ -473,7 +582,9 @@ macro_rules! i2c {
 
             fn slave_read(&mut self, bytes: &mut [u8]) -> Result<(), Error> {
                 let buflen = bytes.len();
-                assert!(buflen < 256 && buflen > 0);
+                if buflen == 0 || buflen > 255 {
+                    return Err(Error::IncorrectFrameSize(buflen))
+                };
 
                 // Set the nbytes START and prepare to receive bytes into `buffer`.
                 self.i2c.cr2().modify(|_, w| unsafe {
@@ -492,7 +603,7 @@ macro_rules! i2c {
                 let mut idx = 0;
                 loop  {
                     // Wait until we have received something.
-                    busy_wait!(self.i2c, rxne, bit_is_set, idx, buflen);
+                    busy_wait!(self.i2c, rxne, bit_is_set, idx, buflen, self.timeout);
 
                     // read byte from wire
                     if idx < buflen {
@@ -509,15 +620,15 @@ i2c!(
     I2C,
     i2c1,
     sda: [
-        (PA10<Output<OpenDrain>>, AltFunction::AF6),
-        (PB7<Output<OpenDrain>>, AltFunction::AF6),
-        (PB9<Output<OpenDrain>>, AltFunction::AF6),
-        (PC14<Output<OpenDrain>>, AltFunction::AF14),
+        (PA10, AltFunction::AF6),
+        (PB7, AltFunction::AF6),
+        (PB9, AltFunction::AF6),
+        (PC14, AltFunction::AF14),
     ],
     scl: [
-        (PA9<Output<OpenDrain>>, AltFunction::AF6),
-        (PB6<Output<OpenDrain>>, AltFunction::AF6),
-        (PB8<Output<OpenDrain>>, AltFunction::AF6),
-        (PB7<Output<OpenDrain>>, AltFunction::AF14),
+        (PA9, AltFunction::AF6),
+        (PB6, AltFunction::AF6),
+        (PB8, AltFunction::AF6),
+        (PB7, AltFunction::AF14),
     ],
 );
@@ -1,7 +1,47 @@
-use crate::i2c::SlaveAddressMask;
+use crate::i2c::{SlaveAddressMask, SmbusMode};
 use crate::time::Hertz;
 use core::cmp;
 
+/// Fields of the TIMINGR register
+///
+/// Start from the computed values of [`Config::timing`] and pass the tweaked result
+/// to [`Config::with_timing`] when a bus needs longer setup or hold times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    /// Timing prescaler, the fields below count periods of `i2c_clk / (presc + 1)`
+    pub presc: u8,
+    /// Data setup time, `scldel + 1` periods
+    pub scldel: u8,
+    /// Data hold time, `sdadel` periods
+    pub sdadel: u8,
+    /// SCL high period, `sclh + 1` periods
+    pub sclh: u8,
+    /// SCL low period, `scll + 1` periods
+    pub scll: u8,
+}
+
+impl Timing {
+    /// Decodes a raw TIMINGR value
+    pub fn from_bits(bits: u32) -> Self {
+        Timing {
+            presc: (bits >> 28) as u8 & 0xf,
+            scldel: (bits >> 20) as u8 & 0xf,
+            sdadel: (bits >> 16) as u8 & 0xf,
+            sclh: (bits >> 8) as u8,
+            scll: bits as u8,
+        }
+    }
+
+    /// Encodes the fields as TIMINGR value, PRESC, SCLDEL and SDADEL are 4 bits wide
+    pub fn bits(&self) -> u32 {
+        (self.presc as u32 & 0xf) << 28
+            | (self.scldel as u32 & 0xf) << 20
+            | (self.sdadel as u32 & 0xf) << 16
+            | (self.sclh as u32) << 8
+            | self.scll as u32
+    }
+}
+
 pub struct Config {
     pub speed: Option<Hertz>,
     pub timing: Option<u32>,
@@ -11,6 +51,8 @@ pub struct Config {
     pub address_11bits: bool,
     pub slave_address_2: u8,
     pub slave_address_mask: SlaveAddressMask,
+    pub busy_timeout: u32,
+    pub smbus: Option<SmbusMode>,
 }
 
 impl Config {
@@ -24,9 +66,12 @@ impl Config {
             address_11bits: false,
             slave_address_2: 0,
             slave_address_mask: SlaveAddressMask::MaskNone,
+            busy_timeout: 0,
+            smbus: None,
         }
     }
 
+    /// Uses a raw TIMINGR value instead of computing it from a bus speed, see [`Timing`]
     pub fn with_timing(timing: u32) -> Self {
         Config {
             timing: Some(timing),
@@ -37,20 +82,48 @@ impl Config {
             address_11bits: false,
             slave_address_2: 0,
             slave_address_mask: SlaveAddressMask::MaskNone,
+            busy_timeout: 0,
+            smbus: None,
         }
     }
 
+    /// Number of status polls the blocking driver waits for each flag before it
+    /// gives up with `Error::Timeout` and resets the peripheral, 0 waits forever
+    pub fn busy_timeout(mut self, polls: u32) -> Self {
+        self.busy_timeout = polls;
+        self
+    }
+
+    /// Enables the SMBus host or device address handling, see [`SmbusMode`]
+    pub fn smbus(mut self, mode: SmbusMode) -> Self {
+        self.smbus = Some(mode);
+        self
+    }
+
     pub fn disable_analog_filter(mut self) -> Self {
         self.analog_filter = false;
         self
     }
 
+    /// Enable the digital noise filter, suppressing spikes up to `cycles` I2C clock
+    /// periods. The filter is 4 bits wide, so `cycles` must be in range 0 .. 15
     pub fn enable_digital_filter(mut self, cycles: u8) -> Self {
-        assert!(cycles <= 16);
+        assert!(cycles <= 15);
         self.digital_filter = cycles;
         self
     }
 
+    /// Digital filter setting as written to DNF, clamped to the 4 bit field
+    pub(crate) fn digital_filter_bits(&self) -> u8 {
+        cmp::min(self.digital_filter, 15)
+    }
+
+    /// Returns the timing fields that are programmed for an I2C kernel clock of `i2c_clk`,
+    /// either computed from the bus speed or decoded from the raw override
+    pub fn timing(&self, i2c_clk: Hertz) -> Timing {
+        Timing::from_bits(self.timing_bits(i2c_clk))
+    }
+
     pub fn timing_bits(&self, i2c_clk: Hertz) -> u32 {
         if let Some(bits) = self.timing {
             return bits;
@@ -94,6 +167,7 @@ impl Config {
     }
 }
 
+/// Default filters and no slave address, so `400.kHz()` can be passed to `i2c` as is
 impl From<Hertz> for Config {
     fn from(speed: Hertz) -> Self {
         Config::new(speed)
@@ -7,10 +7,11 @@ pub mod nonblocking;
 #[cfg(feature = "i2c-nonblocking")]
 pub use nonblocking::*;
 
+pub mod bitbang;
 pub mod config;
 
 use crate::rcc::*;
-pub use config::Config;
+pub use config::{Config, Timing};
 
 #[derive(Debug, Clone, Copy)]
 pub enum SlaveAddressMask {
@@ -24,6 +25,26 @@ pub enum SlaveAddressMask {
     MaskAllBits,
 }
 
+/// SMBus role of the peripheral
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmbusMode {
+    /// Acknowledges the SMBus host address 0b0001000 for host notify, and monitors
+    /// SMBA for alerts once the alert is enabled
+    Host,
+    /// Acknowledges the SMBus device default address 0b1100001, and drives SMBA low
+    /// while the alert is enabled
+    Device,
+}
+
+/// Byte order of 16-bit register values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Most significant byte first, used by most sensors
+    MsbFirst,
+    /// Least significant byte first
+    LsbFirst,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum I2cResult<'a> {
     Data(u16, I2cDirection, &'a [u8]), // contains address, direction and data slice reference
@@ -51,18 +72,36 @@ pub enum Error {
     BusError,
     ArbitrationLost,
     IncorrectFrameSize(usize),
+    /// The expected flag didn't show up within `Config::busy_timeout` polls, e.g. a
+    /// slave holds SCL low. The bit banged master reports SCL held low for too long
+    /// the same way.
+    Timeout,
 }
 
 /// I2C SDA pin
+///
+/// Pass the plain pin, `setup` switches it to alternate function open drain itself.
+/// `release` puts the pad back into its reset state, the pin is returned in the
+/// default (analog) mode.
 pub trait SDAPin<I2C> {
+    /// The pin type handed back by `release`
+    type Released;
+
     fn setup(&self);
-    fn release(self) -> Self;
+    fn release(self) -> Self::Released;
 }
 
 /// I2C SCL pin
+///
+/// Pass the plain pin, `setup` switches it to alternate function open drain itself.
+/// `release` puts the pad back into its reset state, the pin is returned in the
+/// default (analog) mode.
 pub trait SCLPin<I2C> {
+    /// The pin type handed back by `release`
+    type Released;
+
     fn setup(&self);
-    fn release(self) -> Self;
+    fn release(self) -> Self::Released;
 }
 
 pub trait I2cExt<I2C> {
@@ -84,10 +123,12 @@ pub struct I2c<I2C, SDA, SCL> {
     i2c: I2C,
     sda: SDA,
     scl: SCL,
+    timeout: u32,
 }
 
+/// I2C abstraction, `N` is the size of the internal transfer buffer
 #[cfg(feature = "i2c-nonblocking")]
-pub struct I2c<I2C, SDA, SCL> {
+pub struct I2c<I2C, SDA, SCL, const N: usize = 255> {
     i2c: I2C,
     sda: SDA,
     scl: SCL,
@@ -98,5 +139,5 @@ pub struct I2c<I2C, SDA, SCL> {
     errors: usize,            // global error counter, reset on read
     length_write_read: usize, // for a master write_read operation this remembers the size of the read operation
     // for a slave device this must be 0
-    data: [u8; 255], // during transfer the driver will be the owner of the buffer
+    data: [u8; N], // during transfer the driver will be the owner of the buffer
 }
@@ -1,8 +1,51 @@
 //! I2C
+//!
+//! # Sharing the driver with an interrupt handler
+//!
+//! The master/slave calls and `check_isr_flags` all work on the same transfer state
+//! (`data`, `index`, `length`): `master_write` copies into the buffer the state machine
+//! then sends from, and the received bytes end up in that buffer as well. Splitting the
+//! driver into a controller and a worker half would not remove that coupling, both
+//! halves would still need a critical section around every access. So instead of a
+//! `split()` the driver is meant to be shared the usual way:
+//!
+//! ```ignore
+//! static I2C: Mutex<RefCell<Option<I2c<I2C, PB7<Output<OpenDrain>>, PB6<Output<OpenDrain>>>>>> =
+//!     Mutex::new(RefCell::new(None));
+//!
+//! // main loop
+//! interrupt::free(|cs| {
+//!     if let Some(i2c) = I2C.borrow(cs).borrow_mut().as_mut() {
+//!         i2c.master_write(0x50, &[0x00, 0x10]).ok();
+//!     }
+//! });
+//!
+//! // I2C interrupt
+//! interrupt::free(|cs| {
+//!     if let Some(i2c) = I2C.borrow(cs).borrow_mut().as_mut() {
+//!         if let Ok(I2cResult::Data(addr, dir, data)) = i2c.check_isr_flags() {
+//!             // handle the finished transfer
+//!         }
+//!     }
+//! });
+//! ```
+//!
+//! The critical sections are short, `master_write` only copies the buffer and starts
+//! the transfer, the bytes themselves are moved by the interrupt.
+//!
+//! # Buffer size
+//!
+//! The transfer buffer is part of the driver, its size `N` defaults to 255 bytes and
+//! bounds every transfer, including the read phase of `master_write_read`. A read
+//! phase longer than 255 bytes needs a larger buffer, pick it with the constructor:
+//!
+//! ```ignore
+//! let i2c = I2c::<_, _, _, 512>::i2c(dp.I2C, sda, scl, config, &mut rcc);
+//! ```
 use crate::gpio::*;
 use crate::gpio::{AltFunction, OpenDrain, Output};
 use crate::i2c::config::Config;
-use crate::i2c::{Error, I2c, I2cDirection, I2cExt, I2cResult, SCLPin, SDAPin};
+use crate::i2c::{Error, I2c, I2cDirection, I2cExt, I2cResult, SCLPin, SDAPin, SmbusMode};
 use crate::rcc::*;
 use crate::stm32::I2C;
 use nb::Error::{Other, WouldBlock};
@@ -43,11 +86,14 @@ pub trait I2cControl {
 ///
 pub trait I2cMaster {
     /// Send the bytes in the given data buffer to the bus. The data is copied to the internal buffer.
+    /// An empty buffer or one longer than 255 bytes is rejected with IncorrectFrameSize
     fn master_write(&mut self, addr: u16, data: &[u8]) -> nb::Result<(), Error>;
 
     /// Send the bytes in the given data buffer to the bus. The data is copied to the internal buffer.
-    /// After the first write did end succesfully, in the irq function the read is started
-    fn master_write_read(&mut self, addr: u16, data: &[u8], read_len: u8) -> nb::Result<(), Error>;
+    /// After the first write did end succesfully, in the irq function the read is started.
+    /// The read phase may be longer than 255 bytes (up to the size of the internal buffer),
+    /// it is then split into several NBYTES chunks using RELOAD
+    fn master_write_read(&mut self, addr: u16, data: &[u8], read_len: u16) -> nb::Result<(), Error>;
 
     /// Receive bytes from the addressed slave. The data is copied into the internal buffer.
     /// If the bus is not idle the function will return with wouldblock,
@@ -87,8 +133,14 @@ pub trait I2cSlave {
 
     /// Set and enable the (7 bit) adress. To keep the interface generic, only slave address 1 can be set
     fn set_address(&mut self, address: u16);
+
+    /// Disable both own addresses, the peripheral no longer acknowledges as a slave
+    fn disable_slave(&mut self);
 }
 
+/// Largest transfer the NBYTES field can describe without RELOAD
+const MAX_NBYTES: usize = 255;
+
 /// Sequence to flush the RXDR register. This resets the TXIS and TXE flags
 macro_rules! flush_rxdr {
     ($i2c:expr) => {
@@ -101,29 +153,37 @@ macro_rules! flush_rxdr {
 
 macro_rules! i2c {
     ($I2CX:ident, $i2cx:ident,
-        sda: [ $(($PSDA:ty, $AFSDA:expr),)+ ],
-        scl: [ $(($PSCL:ty, $AFSCL:expr),)+ ],
+        sda: [ $(($PSDA:ident, $AFSDA:expr),)+ ],
+        scl: [ $(($PSCL:ident, $AFSCL:expr),)+ ],
     ) => {
         $(
-            impl SDAPin<$I2CX> for $PSDA {
+            impl<MODE> SDAPin<$I2CX> for $PSDA<MODE> {
+                type Released = $PSDA<DefaultMode>;
+
                 fn setup(&self) {
+                    self.set_open_drain();
                     self.set_alt_mode($AFSDA)
                 }
 
-                fn release(self) -> Self {
-                    self.into_open_drain_output()
+                fn release(self) -> Self::Released {
+                    self.set_push_pull();
+                    self.into_analog()
                 }
             }
         )+
 
         $(
-            impl SCLPin<$I2CX> for $PSCL {
+            impl<MODE> SCLPin<$I2CX> for $PSCL<MODE> {
+                type Released = $PSCL<DefaultMode>;
+
                 fn setup(&self) {
+                    self.set_open_drain();
                     self.set_alt_mode($AFSCL)
                 }
 
-                fn release(self) -> Self {
-                    self.into_open_drain_output()
+                fn release(self) -> Self::Released {
+                    self.set_push_pull();
+                    self.into_analog()
                 }
             }
         )+
@@ -144,7 +204,7 @@ macro_rules! i2c {
             }
         }
 
-        impl<SDA, SCL> I2c<$I2CX, SDA, SCL> where
+        impl<SDA, SCL, const N: usize> I2c<$I2CX, SDA, SCL, N> where
             SDA: SDAPin<$I2CX>,
             SCL: SCLPin<$I2CX>
         {
@@ -164,16 +224,24 @@ macro_rules! i2c {
                 let timing_bits = config.timing_bits(rcc.clocks.apb_clk);
                 i2c.timingr.write(|w| unsafe { w.bits(timing_bits) });
 
-                // Enable the I2C processing
+                // The filters can only be changed while PE is cleared
                 i2c.cr1.modify(|_, w| unsafe {
-                    w.pe()
-                        .set_bit()
-                        .dnf()
-                        .bits(config.digital_filter)
+                    w.dnf()
+                        .bits(config.digital_filter_bits())
                         .anfoff()
                         .bit(!config.analog_filter)
                 });
 
+                i2c.cr1.modify(|_, w| {
+                    w.smbhen()
+                        .bit(config.smbus == Some(SmbusMode::Host))
+                        .smbden()
+                        .bit(config.smbus == Some(SmbusMode::Device))
+                });
+
+                // Enable the I2C processing
+                i2c.cr1.modify(|_, w| w.pe().set_bit());
+
                 if config.slave_address_1 > 0 {
                     i2c.oar1.write(|w| unsafe {
                         w.oa1().bits(config.slave_address_1)
@@ -204,15 +272,31 @@ macro_rules! i2c {
                     length:0,
                     errors:0,
                     length_write_read:0,
-                    data:[0_u8;255]
+                    data:[0_u8; N]
                 }
             }
-            pub fn release(self) -> ($I2CX, SDA, SCL) {
+            /// Enables the SMBus alert: a host detects a falling edge on SMBA, a device
+            /// drives SMBA low. The SMBA pin itself isn't set up by the driver.
+            pub fn enable_alert(&mut self, enable: bool) {
+                self.i2c.cr1.modify(|_, w| w.alerten().bit(enable));
+            }
+
+            /// Returns true if a host detected an SMBus alert
+            pub fn is_alert_pending(&self) -> bool {
+                self.i2c.isr.read().alert().bit_is_set()
+            }
+
+            /// Clears the SMBus alert flag
+            pub fn clear_alert(&mut self) {
+                self.i2c.icr.write(|w| w.alertcf().set_bit());
+            }
+
+            pub fn release(self) -> ($I2CX, SDA::Released, SCL::Released) {
                 (self.i2c, self.sda.release(), self.scl.release())
             }
         } // I2c
 
-        impl<SDA, SCL> I2cControl for I2c<$I2CX, SDA, SCL> {
+        impl<SDA, SCL, const N: usize> I2cControl for I2c<$I2CX, SDA, SCL, N> {
             /// Starts listening for an interrupt event
             fn listen(&mut self) {
                 self.i2c.cr1.modify(|_, w|
@@ -341,10 +425,11 @@ macro_rules! i2c {
                         self.length = self.length_write_read;
                         self.length_write_read = 0;
                         self.index = 0;
+                        let chunk = core::cmp::min(self.length, MAX_NBYTES);
                         self.i2c.cr2.write(|w| unsafe {
                             w
                                 // Set number of bytes to transfer
-                                .nbytes().bits(self.length as u8)
+                                .nbytes().bits(chunk as u8)
                                 // Set address to transfer to/from
                                 .sadd().bits((self.address << 1) as u16)
                                 // 7-bit addressing mode
@@ -353,7 +438,8 @@ macro_rules! i2c {
                                 .rd_wrn().set_bit()
                                 // Automatic end mode
                                 .autoend().set_bit()
-                                .reload().clear_bit()
+                                // Reload if the read phase doesn't fit in one NBYTES chunk
+                                .reload().bit(self.length > MAX_NBYTES)
                                 // Start transfer
                                 .start().set_bit()
                         });
@@ -375,6 +461,16 @@ macro_rules! i2c {
                         return Err(Other(Error::IncorrectFrameSize(self.index)))
                     }
                 } else
+                if isr.tcr().bit_is_set() && self.index < self.length {
+                    // Master read longer than 255 bytes: the previous chunk is complete, load the next one.
+                    // A slave receive never gets here, its length is capped to a single chunk.
+                    let remaining = self.length - self.index;
+                    self.i2c.cr2.modify(|_, w| unsafe {
+                        w.nbytes().bits(core::cmp::min(remaining, MAX_NBYTES) as u8)
+                        .reload().bit(remaining > MAX_NBYTES)
+                    });
+                    return Err( WouldBlock)
+                } else
                 if isr.tcr().bit_is_set() {
                     // This condition Will only happen when reload == 1 and sbr == 1 (slave) and nbytes was written.
                     // Send a NACK, set nbytes to clear tcr flag
@@ -399,10 +495,10 @@ macro_rules! i2c {
                         }  else  {
                             // Start the master write slave read transaction fully automatically here
                             // Set the nbytes to the max size and prepare to receive bytes into `buffer`.
-                            self.length = self.data.len();
+                            self.length = core::cmp::min(N, MAX_NBYTES);
                             self.index = 0;
                             self.i2c.cr2.modify(|_, w| unsafe {
-                                // Set number of bytes to transfer: as many as internal buffer
+                                // Set number of bytes to transfer: as many as internal buffer, up to one chunk
                                 w.nbytes().bits(self.length as u8)
                                 // during sending nbytes automatically send a ACK, stretch clock after last byte
                                 .reload().set_bit()
@@ -420,7 +516,7 @@ macro_rules! i2c {
             } // check_isr_flags
         } // i2c
 
-        impl<SDA, SCL> I2cMaster for I2c<$I2CX, SDA, SCL> {
+        impl<SDA, SCL, const N: usize> I2cMaster for I2c<$I2CX, SDA, SCL, N> {
 
 
             fn master_write(&mut self, addr: u16, data: &[u8]) -> nb::Result<(), Error>{
@@ -428,9 +524,11 @@ macro_rules! i2c {
                 if self.i2c.cr2.read().start().bit_is_set() {
                     return Err(nb::Error::WouldBlock)
                 };
-                self.watchdog = 10;
                 let buflen = data.len();
-                assert!(buflen < 256 && buflen > 0);
+                if buflen == 0 || buflen > 255 || buflen > N {
+                    return Err(Other(Error::IncorrectFrameSize(buflen)))
+                };
+                self.watchdog = 10;
                 self.length = buflen;
                 self.data[..buflen].copy_from_slice(data);
                 self.index = 0;
@@ -454,14 +552,19 @@ macro_rules! i2c {
                 // in non-blocking mode the result is not yet available
                 Ok (())
             }
-            fn master_write_read(&mut self, addr: u16, data: &[u8], read_len:u8) -> nb::Result<(), Error>{
+            fn master_write_read(&mut self, addr: u16, data: &[u8], read_len: u16) -> nb::Result<(), Error>{
                 // Check if the bus is free
                 if self.i2c.cr2.read().start().bit_is_set() {
                     return Err(nb::Error::WouldBlock)
                 };
-                self.watchdog = 10;
+                if read_len == 0 || read_len as usize > N {
+                    return Err(Other(Error::IncorrectFrameSize(read_len as usize)))
+                };
                 let buflen = data.len();
-                assert!(buflen < 256 && buflen > 0);
+                if buflen == 0 || buflen > 255 || buflen > N {
+                    return Err(Other(Error::IncorrectFrameSize(buflen)))
+                };
+                self.watchdog = 10;
                 self.length = buflen;
                 self.data[..buflen].copy_from_slice(data);
                 self.index = 0;
@@ -493,6 +596,9 @@ macro_rules! i2c {
                 if self.i2c.cr2.read().start().bit_is_set() {
                     return Err(nb::Error::WouldBlock)
                 };
+                if length == 0 || length as usize > N {
+                    return Err(Other(Error::IncorrectFrameSize(length as usize)))
+                };
                 // Flush rxdr register
                 self.watchdog = 10;
                 self.i2c.rxdr.read().rxdata().bits();
@@ -535,7 +641,7 @@ macro_rules! i2c {
             }
         }
 
-        impl<SDA, SCL> I2cSlave for I2c<$I2CX, SDA, SCL> {
+        impl<SDA, SCL, const N: usize> I2cSlave for I2c<$I2CX, SDA, SCL, N> {
 
             fn slave_sbc(&mut self, sbc_enabled: bool)  {
                 // enable acknowlidge control
@@ -555,9 +661,16 @@ macro_rules! i2c {
                 });
             }
 
+            fn disable_slave(&mut self) {
+                self.i2c.oar1.modify(|_, w| w.oa1en().clear_bit());
+                self.i2c.oar2.modify(|_, w| w.oa2en().clear_bit());
+            }
+
             fn slave_write(&mut self, bytes: &[u8]) -> Result<(), Error> {
                 let buflen = bytes.len();
-                assert!(buflen < 256 && buflen > 0);
+                if buflen == 0 || buflen > 255 || buflen > N {
+                    return Err(Error::IncorrectFrameSize(buflen))
+                };
 
                 self.length = buflen;
                 self.data[..buflen].copy_from_slice(bytes);
@@ -592,15 +705,15 @@ i2c!(
     I2C,
     i2c1,
     sda: [
-        (PA10<Output<OpenDrain>>, AltFunction::AF6),
-        (PB7<Output<OpenDrain>>, AltFunction::AF6),
-        (PB9<Output<OpenDrain>>, AltFunction::AF6),
-        (PC14<Output<OpenDrain>>, AltFunction::AF14),
+        (PA10, AltFunction::AF6),
+        (PB7, AltFunction::AF6),
+        (PB9, AltFunction::AF6),
+        (PC14, AltFunction::AF14),
     ],
     scl: [
-        (PA9<Output<OpenDrain>>, AltFunction::AF6),
-        (PB6<Output<OpenDrain>>, AltFunction::AF6),
-        (PB8<Output<OpenDrain>>, AltFunction::AF6),
-        (PB7<Output<OpenDrain>>, AltFunction::AF14),
+        (PA9, AltFunction::AF6),
+        (PB6, AltFunction::AF6),
+        (PB8, AltFunction::AF6),
+        (PB7, AltFunction::AF14),
     ],
 );
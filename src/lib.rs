@@ -9,6 +9,7 @@ extern crate void;
 
 pub extern crate cortex_m;
 pub extern crate embedded_hal as hal;
+pub extern crate embedded_hal_one as hal1;
 pub extern crate nb;
 pub extern crate stm32c0;
 
@@ -28,6 +29,7 @@ pub use crate::stm32::interrupt;
 
 pub mod analog;
 pub mod crc;
+pub mod dma;
 pub mod exti;
 pub mod gpio;
 pub mod i2c;
@@ -36,11 +38,21 @@ pub mod prelude;
 pub mod rcc;
 pub mod rtc;
 pub mod serial;
+pub mod signature;
 pub mod spi;
+pub mod syscfg;
 pub mod time;
 pub mod timer;
 pub mod watchdog;
 
+/// Performs a system reset
+///
+/// The C0 has no backup registers that survive the reset, a flag for the next boot
+/// (e.g. to enter the bootloader) has to be kept in flash or in the option bytes.
+pub fn reset() -> ! {
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
 #[cfg(feature = "device-selected")]
 mod sealed {
     pub trait Sealed {}
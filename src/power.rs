@@ -1,11 +1,21 @@
 //! Power control
+//!
+//! The C0 main regulator works from a single voltage range, there is no voltage
+//! scaling to select and the maximum system clock is available in every run mode.
+//! It has no programmable voltage detector either, supply monitoring is limited
+//! to the brown-out reset configured with [`Power::set_bor_level`].
 
 use crate::{
     gpio::*,
-    rcc::{Enable, Rcc},
-    stm32::PWR,
+    rcc::{Enable, Peripheral, Rcc},
+    stm32::{FLASH, GPIOA, GPIOB, GPIOC, GPIOD, GPIOF, PWR},
 };
 
+const FLASH_KEY1: u32 = 0x4567_0123;
+const FLASH_KEY2: u32 = 0xCDEF_89AB;
+const OPT_KEY1: u32 = 0x0819_2A3B;
+const OPT_KEY2: u32 = 0x4C5D_6E7F;
+
 pub enum LowPowerMode {
     StopMode1 = 0b000,
     StopMode2 = 0b001,
@@ -28,6 +38,41 @@ pub enum WakeUp {
     Line6,
 }
 
+/// Brown-out reset threshold
+///
+/// The reset is released above the rising threshold and asserted below the falling
+/// one, roughly 2.1/2.0 V for `Level1` up to 2.9/2.8 V for `Level4`. See the
+/// datasheet for the exact values.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BorLevel {
+    Level1 = 0b00,
+    Level2 = 0b01,
+    Level3 = 0b10,
+    Level4 = 0b11,
+}
+
+/// GPIO port, selects the pins switched to analog by [`Power::prepare_for_sleep`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GpioPort {
+    A,
+    B,
+    C,
+    D,
+    F,
+}
+
+impl GpioPort {
+    fn peripheral(self) -> Peripheral {
+        match self {
+            GpioPort::A => Peripheral::GPIOA,
+            GpioPort::B => Peripheral::GPIOB,
+            GpioPort::C => Peripheral::GPIOC,
+            GpioPort::D => Peripheral::GPIOD,
+            GpioPort::F => Peripheral::GPIOF,
+        }
+    }
+}
+
 pub struct Power {
     rb: PWR,
 }
@@ -68,10 +113,8 @@ impl Power {
         self.rb.scr().write(|w| w.csbf().set_bit());
     }
 
-    pub fn enable_wakeup_lane<L: Into<WakeUp>>(&mut self, lane: L, edge: SignalEdge) {
-        assert!(edge != SignalEdge::All);
-
-        let edge = edge == SignalEdge::Falling;
+    pub fn enable_wakeup_lane<L: Into<WakeUp>>(&mut self, lane: L, edge: SingleEdge) {
+        let edge = edge == SingleEdge::Falling;
         match lane.into() {
             WakeUp::Line1 => {
                 self.rb.cr3().modify(|_, w| w.ewup1().set_bit());
@@ -108,6 +151,102 @@ impl Power {
         }
     }
 
+    /// Returns the brown-out reset level from the option bytes, `None` if BOR is disabled
+    pub fn bor_level(&self, flash: &FLASH) -> Option<BorLevel> {
+        let optr = flash.optr().read();
+        if optr.bor_en().bit_is_clear() {
+            return None;
+        }
+        Some(match optr.borr_lev().bits() {
+            0b00 => BorLevel::Level1,
+            0b01 => BorLevel::Level2,
+            0b10 => BorLevel::Level3,
+            _ => BorLevel::Level4,
+        })
+    }
+
+    /// Programs the brown-out reset level into the option bytes, `None` disables BOR
+    ///
+    /// The new option bytes are loaded with `OBL_LAUNCH`, which resets the device, so
+    /// this function does not return.
+    pub fn set_bor_level(&mut self, flash: &mut FLASH, level: Option<BorLevel>) -> ! {
+        while flash.sr().read().bsy1().bit_is_set() {}
+        if flash.cr().read().lock().bit_is_set() {
+            flash.keyr().write(|w| unsafe { w.bits(FLASH_KEY1) });
+            flash.keyr().write(|w| unsafe { w.bits(FLASH_KEY2) });
+        }
+        if flash.cr().read().optlock().bit_is_set() {
+            flash.optkeyr().write(|w| unsafe { w.bits(OPT_KEY1) });
+            flash.optkeyr().write(|w| unsafe { w.bits(OPT_KEY2) });
+        }
+
+        let lev = level.unwrap_or(BorLevel::Level1) as u8;
+        flash.optr().modify(|_, w| unsafe {
+            w.bor_en()
+                .bit(level.is_some())
+                .borr_lev()
+                .bits(lev)
+                .borf_lev()
+                .bits(lev)
+        });
+        flash.cr().modify(|_, w| w.optstrt().set_bit());
+        while flash.sr().read().bsy1().bit_is_set() {}
+
+        flash.cr().modify(|_, w| w.obl_launch().set_bit());
+        loop {
+            cortex_m::asm::nop();
+        }
+    }
+
+    /// Cuts the power drawn by unused peripherals and pins before entering a low power mode
+    ///
+    /// The pins in the `analog` masks, given per GPIO port, are switched to analog mode
+    /// without pull resistors, the state with the lowest leakage. Only list pins that no
+    /// driver still uses, and keep the debug pins PA13/PA14 if a probe is attached.
+    /// Afterwards the clocks of all peripherals not in `keep` are disabled. PWR itself
+    /// stays enabled so the low power mode can still be selected, and DBG stays enabled
+    /// so a debugger stays attached. Neither has to be listed in `keep`, disable them
+    /// with [`Rcc::set_peripheral_clock`] afterwards if needed.
+    pub fn prepare_for_sleep(
+        &mut self,
+        rcc: &mut Rcc,
+        keep: &[Peripheral],
+        analog: &[(GpioPort, u16)],
+    ) {
+        for &(port, pins) in analog {
+            let mut moder = 0u32;
+            for i in (0..16).filter(|i| pins & (1 << i) != 0) {
+                moder |= 0b11 << (2 * i);
+            }
+            let enabled = rcc.is_peripheral_clock_enabled(port.peripheral());
+            rcc.set_peripheral_clock(port.peripheral(), true);
+            macro_rules! set_analog {
+                ($GPIOX:ident) => {{
+                    let gpio = unsafe { &(*$GPIOX::ptr()) };
+                    gpio.pupdr()
+                        .modify(|r, w| unsafe { w.bits(r.bits() & !moder) });
+                    gpio.moder()
+                        .modify(|r, w| unsafe { w.bits(r.bits() | moder) });
+                }};
+            }
+            match port {
+                GpioPort::A => set_analog!(GPIOA),
+                GpioPort::B => set_analog!(GPIOB),
+                GpioPort::C => set_analog!(GPIOC),
+                GpioPort::D => set_analog!(GPIOD),
+                GpioPort::F => set_analog!(GPIOF),
+            }
+            rcc.set_peripheral_clock(port.peripheral(), enabled);
+        }
+
+        for &peripheral in Peripheral::ALL {
+            let always_on = peripheral == Peripheral::PWR || peripheral == Peripheral::DBG;
+            if !always_on && !keep.contains(&peripheral) {
+                rcc.set_peripheral_clock(peripheral, false);
+            }
+        }
+    }
+
     pub fn set_mode(&mut self, _mode: PowerMode) {
         todo!();
         // match mode {
@@ -1,3 +1,6 @@
+//! Extension traits of all the peripheral drivers, imported unnamed so they only
+//! provide method syntax. Every driver constructor trait (`XExt`) is re-exported here.
+
 pub use crate::analog::adc::AdcExt as _;
 pub use crate::crc::CrcExt as _;
 pub use crate::exti::ExtiExt as _;
@@ -10,10 +13,14 @@ pub use crate::rcc::RccExt as _;
 pub use crate::rtc::RtcExt as _;
 pub use crate::serial::SerialExt as _;
 pub use crate::spi::SpiExt as _;
+pub use crate::syscfg::SysCfgExt as _;
 pub use crate::time::U32Ext as _;
 pub use crate::timer::delay::DelayExt as _;
+#[cfg(feature = "rtic")]
+pub use crate::timer::monotonic::MonoTimerExt as _;
 pub use crate::timer::opm::OpmExt as _;
 pub use crate::timer::pwm::PwmExt as _;
+pub use crate::timer::pwm_input::PwmInputExt as _;
 pub use crate::timer::qei::QeiExt as _;
 pub use crate::timer::stopwatch::StopwatchExt as _;
 pub use crate::timer::TimerExt as _;
@@ -71,6 +71,11 @@ where
     }
 }
 
+/// Microcontroller clock output
+///
+/// MCO is implemented for every pad it can be routed to, PA8, PA9 and PF2 (all AF0).
+/// The pins share the one MCO source and prescaler in `cfgr`, so only one `Mco` should
+/// be enabled at a time.
 pub trait MCOExt<PIN> {
     fn mco(self, src: MCOSrc, psc: Prescaler, rcc: &mut Rcc) -> Mco<PIN>;
     fn release(self) -> PIN;
@@ -1,3 +1,4 @@
+use super::{RccError, MAX_SYS_FREQ};
 use crate::time::Hertz;
 
 /// Prescaler
@@ -102,6 +103,45 @@ impl Config {
     }
 }
 
+impl Config {
+    pub(crate) fn validate(&self) -> Result<(), RccError> {
+        match self.sys_mux {
+            SysClockSrc::HSE(freq) if freq.raw() < 4_000_000 => {
+                return Err(RccError::HseFrequencyOutOfRange)
+            }
+            SysClockSrc::HSE_BYPASS(freq) if freq.raw() == 0 => {
+                return Err(RccError::HseFrequencyOutOfRange)
+            }
+            SysClockSrc::HSE(freq) | SysClockSrc::HSE_BYPASS(freq) if freq.raw() > MAX_SYS_FREQ => {
+                return Err(RccError::SysClockTooHigh)
+            }
+            SysClockSrc::LSE(freq) if freq.raw() != 32_768 => {
+                return Err(RccError::LseFrequencyOutOfRange)
+            }
+            SysClockSrc::LSE_BYPASS(freq) if freq.raw() == 0 || freq.raw() > 1_000_000 => {
+                return Err(RccError::LseFrequencyOutOfRange)
+            }
+            SysClockSrc::HSI(Prescaler::Div256 | Prescaler::Div512) => {
+                return Err(RccError::InvalidPrescaler)
+            }
+            _ => {}
+        }
+
+        // HPRE has no /32 setting, PPRE stops at /16
+        if let Prescaler::Div32 = self.ahb_psc {
+            return Err(RccError::InvalidPrescaler);
+        }
+        match self.apb_psc {
+            Prescaler::Div32
+            | Prescaler::Div64
+            | Prescaler::Div128
+            | Prescaler::Div256
+            | Prescaler::Div512 => Err(RccError::InvalidPrescaler),
+            _ => Ok(()),
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Config {
         Config {
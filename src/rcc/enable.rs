@@ -107,6 +107,40 @@ macro_rules! bus {
             $(bus_smenable!($PER => $smen);)?
             $(bus_reset!($PER => $rst);)?
         )+
+
+        /// Peripheral selected at runtime, see [`Rcc::set_peripheral_clock`]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Peripheral {
+            $($PER,)+
+        }
+
+        impl Peripheral {
+            /// All peripherals with a clock enable bit
+            pub const ALL: &'static [Peripheral] = &[$(Peripheral::$PER,)+];
+        }
+
+        impl Rcc {
+            /// Enables or disables the clock of a peripheral chosen at runtime
+            ///
+            /// This is the runtime counterpart of [`Enable`], e.g. for a power manager that
+            /// gates a list of unused peripherals before going to sleep.
+            pub fn set_peripheral_clock(&mut self, peripheral: Peripheral, enable: bool) {
+                match peripheral {
+                    $(Peripheral::$PER => if enable {
+                        <crate::stm32::$PER as Enable>::enable(self)
+                    } else {
+                        <crate::stm32::$PER as Enable>::disable(self)
+                    },)+
+                }
+            }
+
+            /// Checks if the clock of a peripheral chosen at runtime is enabled
+            pub fn is_peripheral_clock_enabled(&self, peripheral: Peripheral) -> bool {
+                match peripheral {
+                    $(Peripheral::$PER => <crate::stm32::$PER as Enable>::is_enabled(),)+
+                }
+            }
+        }
     }
 }
 
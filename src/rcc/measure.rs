@@ -0,0 +1,90 @@
+//! Low speed clock measurement
+//!
+//! TI1 of TIM16 can be connected to LSI or LSE. Capturing every eighth edge against
+//! the timer clock gives the low speed clock frequency, with the timer clock as
+//! reference. Measuring LSI this way corrects RTC and watchdog timing, measuring the
+//! precise LSE shows the error of the timer clock (e.g. HSI) instead.
+use crate::rcc::*;
+use crate::stm32::TIM16;
+use crate::time::Hertz;
+use core::cmp;
+
+/// Number of capture intervals averaged per measurement
+const SAMPLES: u32 = 16;
+
+/// Edges of the measured clock per capture, the IC1 prescaler is set to 8
+const EDGES_PER_CAPTURE: u32 = 8;
+
+/// Timer overflows without a capture after which the clock is taken as stopped
+const MAX_OVERFLOWS: u32 = 2;
+
+impl Rcc {
+    /// Measures the LSI frequency against the APB timer clock, starting LSI if needed
+    ///
+    /// Returns `None` if no edge is captured for a couple of timer overflows.
+    pub fn measure_lsi(&mut self, tim: &mut TIM16) -> Option<Hertz> {
+        self.enable_lsi();
+        measure(self, tim, 0b0001)
+    }
+
+    /// Measures the LSE frequency against the APB timer clock
+    ///
+    /// LSE has to be running already. The result differs from 32.768 kHz by the
+    /// relative error of the timer clock. Returns `None` if LSE isn't running, e.g.
+    /// when no crystal is fitted.
+    pub fn measure_lse(&mut self, tim: &mut TIM16) -> Option<Hertz> {
+        measure(self, tim, 0b0010)
+    }
+}
+
+fn measure(rcc: &mut Rcc, tim: &mut TIM16, ti1sel: u8) -> Option<Hertz> {
+    TIM16::enable(rcc);
+    TIM16::reset(rcc);
+
+    tim.tisel().write(|w| unsafe { w.ti1sel().bits(ti1sel) });
+    // CC1 captures TI1 on every eighth rising edge
+    tim.ccmr1_input()
+        .write(|w| unsafe { w.cc1s().bits(0b01).ic1psc().bits(0b11) });
+    tim.ccer().write(|w| w.cc1e().set_bit());
+    tim.psc().write(|w| unsafe { w.psc().bits(0) });
+    tim.arr().write(|w| unsafe { w.bits(0xffff) });
+    tim.egr().write(|w| w.ug().set_bit());
+    tim.sr().write(|w| unsafe { w.bits(0) });
+    tim.cr1().write(|w| w.cen().set_bit());
+
+    // Reading CCR1 clears CC1IF, the 16 bit interval is 12000 ticks for LSI at 48 MHz.
+    // A stopped clock never captures, give up after a few overflows of the counter.
+    let capture = |tim: &TIM16| {
+        let mut overflows = 0;
+        loop {
+            let sr = tim.sr().read();
+            if sr.cc1if().bit_is_set() {
+                return Some(tim.ccr1().read().bits() as u16);
+            }
+            if sr.uif().bit_is_set() {
+                // NOTE(unsafe) flags are cleared by writing 0, writing 1 has no effect
+                tim.sr().write(|w| unsafe { w.bits(!1) });
+                overflows += 1;
+                if overflows > MAX_OVERFLOWS {
+                    return None;
+                }
+            }
+        }
+    };
+    let ticks = capture(tim).and_then(|mut last| {
+        let mut ticks = 0u32;
+        for _ in 0..SAMPLES {
+            let next = capture(tim)?;
+            ticks += next.wrapping_sub(last) as u32;
+            last = next;
+        }
+        Some(ticks)
+    });
+
+    tim.cr1().write(|w| w.cen().clear_bit());
+    TIM16::disable(rcc);
+
+    let clk = rcc.clocks.apb_tim_clk.raw() as u64;
+    let edges = (SAMPLES * EDGES_PER_CAPTURE) as u64;
+    ticks.map(|ticks| Hertz::from_raw((clk * edges / cmp::max(ticks, 1) as u64) as u32))
+}
@@ -1,17 +1,36 @@
 use crate::stm32::{rcc, RCC};
-use crate::time::Hertz;
+use crate::time::{Hertz, MicroSecond};
+use core::cmp;
 use fugit::RateExtU32;
 
 mod clockout;
 mod config;
 mod enable;
+mod measure;
 
 pub use clockout::*;
 pub use config::*;
+pub use enable::Peripheral;
 
 /// HSI frequency
 pub const HSI_FREQ: u32 = 48_000_000;
 
+/// Maximum SYSCLK, HCLK and PCLK frequency
+pub const MAX_SYS_FREQ: u32 = 48_000_000;
+
+/// Clock tree configuration errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RccError {
+    /// HSE crystal below 4 MHz, or no external clock frequency given
+    HseFrequencyOutOfRange,
+    /// LSE crystal other than 32.768 kHz, or external clock above 1 MHz
+    LseFrequencyOutOfRange,
+    /// System clock above 48 MHz, the limit for HCLK and PCLK as well
+    SysClockTooHigh,
+    /// Prescaler not supported by the selected bus
+    InvalidPrescaler,
+}
+
 /// Clock frequencies
 #[derive(Clone, Copy)]
 pub struct Clocks {
@@ -57,7 +76,24 @@ impl core::ops::Deref for Rcc {
 
 impl Rcc {
     /// Apply clock configuration
+    ///
+    /// Panics if the configuration is out of range, see [`Rcc::try_freeze`].
     pub fn freeze(self, cfg: Config) -> Self {
+        match self.try_freeze(cfg) {
+            Ok(rcc) => rcc,
+            Err((_, err)) => panic!("invalid clock configuration: {:?}", err),
+        }
+    }
+
+    /// Apply clock configuration after checking it against the device limits
+    ///
+    /// Nothing is written to the RCC if the configuration is rejected, the untouched
+    /// `Rcc` is handed back with the error so another configuration can be tried.
+    pub fn try_freeze(self, cfg: Config) -> Result<Self, (Self, RccError)> {
+        if let Err(err) = cfg.validate() {
+            return Err((self, err));
+        }
+
         let (sys_clk, sw_bits) = match cfg.sys_mux {
             SysClockSrc::HSE(freq) => {
                 self.enable_hse(false);
@@ -108,12 +144,18 @@ impl Rcc {
             Prescaler::Div512 => (sys_freq / 512, 0b1111),
             _ => (sys_clk.raw(), 0b0000),
         };
-        let (apb_freq, apb_tim_freq, apb_psc_bits) = match cfg.apb_psc {
-            Prescaler::Div2 => (ahb_freq / 2, ahb_freq, 0b100),
-            Prescaler::Div4 => (ahb_freq / 4, ahb_freq / 2, 0b101),
-            Prescaler::Div8 => (ahb_freq / 8, ahb_freq / 4, 0b110),
-            Prescaler::Div16 => (ahb_freq / 16, ahb_freq / 8, 0b111),
-            _ => (ahb_freq, ahb_freq, 0b000),
+        let (apb_freq, apb_psc_bits) = match cfg.apb_psc {
+            Prescaler::Div2 => (ahb_freq / 2, 0b100),
+            Prescaler::Div4 => (ahb_freq / 4, 0b101),
+            Prescaler::Div8 => (ahb_freq / 8, 0b110),
+            Prescaler::Div16 => (ahb_freq / 16, 0b111),
+            _ => (ahb_freq, 0b000),
+        };
+        // Timer clock is PCLK if the APB prescaler is 1, 2 x PCLK otherwise
+        let apb_tim_freq = if apb_psc_bits == 0b000 {
+            apb_freq
+        } else {
+            apb_freq * 2
         };
 
         self.cfgr().modify(|_, w| unsafe {
@@ -127,7 +169,7 @@ impl Rcc {
 
         while self.cfgr().read().sws().bits() != sw_bits {}
 
-        Rcc {
+        Ok(Rcc {
             rb: self.rb,
             clocks: Clocks {
                 sys_clk,
@@ -136,7 +178,7 @@ impl Rcc {
                 apb_tim_clk: apb_tim_freq.Hz(),
                 core_clk: (ahb_freq / 8).Hz(),
             },
-        }
+        })
     }
 
     pub(crate) fn enable_hsi(&self) {
@@ -161,6 +203,21 @@ impl Rcc {
         while self.csr1().read().lserdy().bit_is_clear() {}
     }
 
+    /// Starts the LSE crystal, gives up and turns it off again after `timeout`
+    pub(crate) fn try_enable_lse(&self, timeout: MicroSecond) -> bool {
+        self.csr1()
+            .modify(|_, w| w.lseon().set_bit().lsebyp().clear_bit());
+        let cycles_per_us = cmp::max(self.clocks.sys_clk.raw() / 1_000_000, 1);
+        for _ in 0..timeout.ticks() {
+            if self.csr1().read().lserdy().bit_is_set() {
+                return true;
+            }
+            cortex_m::asm::delay(cycles_per_us);
+        }
+        self.csr1().modify(|_, w| w.lseon().clear_bit());
+        false
+    }
+
     pub(crate) fn enable_pwr_clock(&self) {
         self.apbenr1().modify(|_, w| w.pwren().set_bit());
     }
@@ -202,6 +259,8 @@ pub trait RccExt {
     fn constrain(self) -> Rcc;
     /// Constrains the `RCC` peripheral and apply clock configuration
     fn freeze(self, rcc_cfg: Config) -> Rcc;
+    /// Constrains the `RCC` peripheral and apply a validated clock configuration
+    fn try_freeze(self, rcc_cfg: Config) -> Result<Rcc, (Rcc, RccError)>;
 }
 
 impl RccExt for RCC {
@@ -215,6 +274,10 @@ impl RccExt for RCC {
     fn freeze(self, rcc_cfg: Config) -> Rcc {
         self.constrain().freeze(rcc_cfg)
     }
+
+    fn try_freeze(self, rcc_cfg: Config) -> Result<Rcc, (Rcc, RccError)> {
+        self.constrain().try_freeze(rcc_cfg)
+    }
 }
 
 /// Bus associated to peripheral
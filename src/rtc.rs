@@ -1,4 +1,7 @@
 //! Real Time Clock
+//!
+//! The C0 has no TAMP block: there are no tamper inputs and no backup registers
+//! to erase, external events can only be captured with the RTC timestamp.
 use crate::gpio::*;
 use crate::rcc::{RTCSrc, Rcc};
 use crate::stm32::RTC;
@@ -16,6 +19,19 @@ pub enum RtcCalibrationFrequency {
     F512Hz,
 }
 
+/// RTC error
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Error {
+    /// The two digit year field only covers 1970 to 2069
+    YearOutOfRange,
+    /// The shadow registers didn't resynchronize, the RTC clock is probably not running
+    SyncTimeout,
+}
+
+/// Status polls before giving up on the shadow register synchronization, it takes two
+/// RTC clock periods which is well below a millisecond for any RTC clock source
+const SYNC_TIMEOUT: u32 = 100_000;
+
 pub enum Event {
     WakeupTimer,
     AlarmA,
@@ -107,11 +123,59 @@ pub struct Rtc {
 }
 
 impl Rtc {
+    /// The prescalers are set for 1 Hz from a 32.768 kHz LSE or the 32 kHz LSI. The HSE
+    /// reaches the RTC divided by 32, call `set_clock_frequency` with that value.
     pub fn new(rtc: RTC, src: RTCSrc, rcc: &mut Rcc) -> Self {
         rcc.enable_rtc(src);
-        Rtc { rb: rtc }
+        let mut rtc = Rtc { rb: rtc };
+        match src {
+            RTCSrc::LSE | RTCSrc::LSE_BYPASS => rtc.set_clock_frequency(32_768.Hz()),
+            RTCSrc::LSI => rtc.set_clock_frequency(32_000.Hz()),
+            RTCSrc::HSE | RTCSrc::HSE_BYPASS => {}
+        }
+        rtc
+    }
+
+    /// Uses the LSE if the crystal starts within `lse_timeout`, the LSI otherwise, and
+    /// returns the source that was selected
+    pub fn new_auto(rtc: RTC, rcc: &mut Rcc, lse_timeout: MicroSecond) -> (Self, RTCSrc) {
+        let src = if rcc.try_enable_lse(lse_timeout) {
+            RTCSrc::LSE
+        } else {
+            RTCSrc::LSI
+        };
+        (Rtc::new(rtc, src, rcc), src)
+    }
+
+    /// Sets the asynchronous (7 bit) and synchronous (15 bit) prescalers, the calendar
+    /// runs at `rtcclk / ((async_prescaler + 1) * (sync_prescaler + 1))`
+    pub fn set_prescalers(&mut self, async_prescaler: u8, sync_prescaler: u16) {
+        self.modify(|rb| {
+            // PRER has to be written in two separate accesses
+            rb.prer()
+                .modify(|_, w| unsafe { w.prediv_s().bits(sync_prescaler & 0x7fff) });
+            rb.prer()
+                .modify(|_, w| unsafe { w.prediv_a().bits(async_prescaler & 0x7f) });
+        });
     }
 
+    /// Sets the prescalers for a 1 Hz calendar clock from an RTC clock of `freq`
+    ///
+    /// The asynchronous prescaler is kept as high as possible to save power, the
+    /// synchronous one sets the sub-second resolution.
+    pub fn set_clock_frequency(&mut self, freq: Hertz) {
+        let freq = freq.raw();
+        let async_div = (1..=128)
+            .rev()
+            .find(|div| freq % div == 0 && freq / div <= 0x8000)
+            .unwrap_or(128);
+        let sync_div = (freq / async_div).clamp(1, 0x8000);
+        self.set_prescalers((async_div - 1) as u8, (sync_div - 1) as u16);
+    }
+
+    /// Selects the hour format of the calendar registers
+    ///
+    /// The time registers are not converted, so set the time again after switching.
     pub fn set_hour_format(&mut self, fmt: RtcHourFormat) {
         self.modify(|rb| {
             rb.cr()
@@ -119,7 +183,15 @@ impl Rtc {
         });
     }
 
-    pub fn set_date(&mut self, date: &Date) {
+    fn is_h12(&self) -> bool {
+        self.rb.cr().read().fmt().bit()
+    }
+
+    /// Sets the calendar date, the year must be in the range 1970 to 2069
+    pub fn set_date(&mut self, date: &Date) -> Result<(), Error> {
+        if !(1970..=2069).contains(&date.year) {
+            return Err(Error::YearOutOfRange);
+        }
         let (yt, yu) = bcd2_encode(date.year - 1970);
         let (mt, mu) = bcd2_encode(date.month);
         let (dt, du) = bcd2_encode(date.day);
@@ -142,10 +214,16 @@ impl Rtc {
                     .bits(date.day as u8)
             });
         });
+        Ok(())
     }
 
+    /// Sets the time of day
+    ///
+    /// `time.hours` is always 0 to 23, in 12-hour format it is written as 1 to 12 with the
+    /// PM flag. The daylight saving flag is kept in the `bkp` bit of `cr`.
     pub fn set_time(&mut self, time: &Time) {
-        let (ht, hu) = bcd2_encode(time.hours);
+        let (hours, pm) = hours_to_register(time.hours, self.is_h12());
+        let (ht, hu) = bcd2_encode(hours);
         let (mnt, mnu) = bcd2_encode(time.minutes);
         let (st, su) = bcd2_encode(time.seconds);
         self.modify(|rb| {
@@ -163,29 +241,134 @@ impl Rtc {
                     .su()
                     .bits(su)
                     .pm()
-                    .clear_bit()
+                    .bit(pm)
             });
-            rb.cr().modify(|_, w| w.fmt().bit(time.daylight_savings));
+            rb.cr().modify(|_, w| w.bkp().bit(time.daylight_savings));
         });
     }
 
+    /// Returns the time of day, the hours are converted back to 0 to 23 in 12-hour format
     pub fn get_time(&self) -> Time {
-        let timer = self.rb.tr().read();
-        Time::new(
-            bcd2_decode(timer.ht().bits(), timer.hu().bits()).hours(),
-            bcd2_decode(timer.mnt().bits(), timer.mnu().bits()).minutes(),
-            bcd2_decode(timer.st().bits(), timer.su().bits()).secs(),
-            self.rb.cr().read().fmt().bit(),
-        )
+        self.get_datetime().1
     }
 
     pub fn get_date(&self) -> Date {
-        let date = self.rb.dr().read();
-        Date::new(
-            (bcd2_decode(date.yt().bits(), date.yu().bits()) + 1970).year(),
-            bcd2_decode(date.mt().bit() as u8, date.mu().bits()).month(),
-            bcd2_decode(date.dt().bits(), date.du().bits()).day(),
-        )
+        self.get_datetime().0
+    }
+
+    /// Waits until the calendar shadow registers hold the current time
+    ///
+    /// The shadow registers are only updated while the APB clock runs, so call this after
+    /// waking up from Stop mode before reading the calendar. RSF is cleared first and the
+    /// next synchronization is awaited.
+    pub fn wait_for_sync(&mut self) -> Result<(), Error> {
+        self.rb.wpr().write(|w| unsafe { w.bits(0xCA) });
+        self.rb.wpr().write(|w| unsafe { w.bits(0x53) });
+        self.rb.icsr().modify(|_, w| w.rsf().clear_bit());
+        self.rb.wpr().write(|w| unsafe { w.bits(0xFF) });
+        if self.is_synchronized() {
+            Ok(())
+        } else {
+            Err(Error::SyncTimeout)
+        }
+    }
+
+    /// Polls RSF, which is cleared by hardware in init mode, up to `SYNC_TIMEOUT` times
+    fn is_synchronized(&self) -> bool {
+        (0..SYNC_TIMEOUT).any(|_| self.rb.icsr().read().rsf().bit_is_set())
+    }
+
+    /// Reads date and time as one coherent snapshot
+    ///
+    /// Reading `ssr` locks the `tr` and `dr` shadow registers until `dr` is read, so the
+    /// registers are read in that order. The snapshot is repeated until two consecutive
+    /// reads match, which also covers a roll over while the shadow registers resync.
+    /// The first synchronization after init mode is awaited for a bounded time, see
+    /// [`Rtc::wait_for_sync`] for the resynchronization after Stop mode.
+    pub fn get_datetime(&self) -> (Date, Time) {
+        let _ = self.is_synchronized();
+        let mut snapshot = self.read_calendar();
+        loop {
+            let next = self.read_calendar();
+            if next == snapshot {
+                break;
+            }
+            snapshot = next;
+        }
+
+        let (tr, dr) = snapshot;
+        let field = |reg: u32, offset: u32, bits: u32| ((reg >> offset) & ((1 << bits) - 1)) as u8;
+        let cr = self.rb.cr().read();
+        let hours = bcd2_decode(field(tr, 20, 2), field(tr, 16, 4));
+        let time = Time::new(
+            hours_from_register(hours, field(tr, 22, 1) != 0, cr.fmt().bit()).hours(),
+            bcd2_decode(field(tr, 12, 3), field(tr, 8, 4)).minutes(),
+            bcd2_decode(field(tr, 4, 3), field(tr, 0, 4)).secs(),
+            cr.bkp().bit(),
+        );
+        let date = Date::new(
+            (bcd2_decode(field(dr, 20, 4), field(dr, 16, 4)) + 1970).year(),
+            bcd2_decode(field(dr, 12, 1), field(dr, 8, 4)).month(),
+            bcd2_decode(field(dr, 4, 2), field(dr, 0, 4)).day(),
+        );
+        (date, time)
+    }
+
+    /// Raw `tr` and `dr` words, read after `ssr` so they come from the same tick
+    fn read_calendar(&self) -> (u32, u32) {
+        let _ = self.rb.ssr().read();
+        let tr = self.rb.tr().read().bits();
+        let dr = self.rb.dr().read().bits();
+        (tr, dr)
+    }
+
+    /// Returns the current date and time as seconds since 1970-01-01 00:00:00
+    pub fn now_unix(&self) -> u64 {
+        let (date, time) = self.get_datetime();
+        unix_timestamp(&date, &time)
+    }
+
+    /// Returns the time elapsed since an earlier `now_unix` reading, zero if the
+    /// clock was set back in between
+    pub fn duration_since(&self, earlier: u64) -> Second {
+        let secs = self.now_unix().saturating_sub(earlier);
+        Second::from_ticks(secs as u32)
+    }
+
+    /// Captures the calendar on the given edge of the RTC_TS input
+    pub fn enable_timestamp(&mut self, edge: SingleEdge) {
+        self.modify(|rb| {
+            rb.cr().modify(|_, w| w.tse().clear_bit());
+            rb.cr()
+                .modify(|_, w| w.tsedge().bit(edge == SingleEdge::Falling));
+            rb.cr().modify(|_, w| w.tse().set_bit());
+        });
+    }
+
+    /// Returns the last captured timestamp with its subseconds and clears the flag.
+    /// The timestamp registers hold no year, the current calendar year is used.
+    pub fn get_timestamp(&mut self) -> Option<(Date, Time, u16)> {
+        if self.rb.sr().read().tsf().bit_is_clear() {
+            return None;
+        }
+        let ss = self.rb.tsssr().read().ss().bits();
+        let ts_time = self.rb.tstr().read();
+        let ts_date = self.rb.tsdr().read();
+        let cr = self.rb.cr().read();
+        let hours = bcd2_decode(ts_time.ht().bits(), ts_time.hu().bits());
+        let time = Time::new(
+            hours_from_register(hours, ts_time.pm().bit(), cr.fmt().bit()).hours(),
+            bcd2_decode(ts_time.mnt().bits(), ts_time.mnu().bits()).minutes(),
+            bcd2_decode(ts_time.st().bits(), ts_time.su().bits()).secs(),
+            cr.bkp().bit(),
+        );
+        let date = Date::new(
+            self.get_date().year.year(),
+            bcd2_decode(ts_date.mt().bit() as u8, ts_date.mu().bits()).month(),
+            bcd2_decode(ts_date.dt().bits(), ts_date.du().bits()).day(),
+        );
+        self.rb.scr().write(|w| w.ctsf().set_bit());
+        Some((date, time, ss))
     }
 
     pub fn get_week_day(&self) -> u8 {
@@ -195,7 +378,8 @@ impl Rtc {
     pub fn set_alarm_a(&mut self, alarm: impl Into<Alarm>) {
         let alarm = alarm.into();
         let (dt, du) = bcd2_encode(alarm.day.unwrap_or_default());
-        let (ht, hu) = bcd2_encode(alarm.hours.unwrap_or_default());
+        let (hours, pm) = hours_to_register(alarm.hours.unwrap_or_default(), self.is_h12());
+        let (ht, hu) = bcd2_encode(hours);
         let (mt, mu) = bcd2_encode(alarm.minutes.unwrap_or_default());
         let (st, su) = bcd2_encode(alarm.seconds.unwrap_or_default());
 
@@ -216,6 +400,7 @@ impl Rtc {
                 w.du().bits(du);
                 w.ht().bits(ht);
                 w.hu().bits(hu);
+                w.pm().bit(pm);
                 w.mnt().bits(mt);
                 w.mnu().bits(mu);
                 w.st().bits(st);
@@ -385,6 +570,45 @@ rtc_out_pins! {
     PC13<DefaultMode>: (AltFunction::AF3, false),
 }
 
+fn is_leap_year(year: u32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn unix_timestamp(date: &Date, time: &Time) -> u64 {
+    const DAYS_BEFORE_MONTH: [u64; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+
+    let mut days: u64 = (1970..date.year)
+        .map(|year| if is_leap_year(year) { 366 } else { 365 })
+        .sum();
+    let month = date.month.clamp(1, 12) as usize;
+    days += DAYS_BEFORE_MONTH[month - 1];
+    if month > 2 && is_leap_year(date.year) {
+        days += 1;
+    }
+    days += date.day.saturating_sub(1) as u64;
+
+    days * 86_400 + time.hours as u64 * 3_600 + time.minutes as u64 * 60 + time.seconds as u64
+}
+
+/// Converts 0 to 23 hours to the register value and PM flag of the active hour format
+fn hours_to_register(hours: u32, h12: bool) -> (u32, bool) {
+    if !h12 {
+        return (hours, false);
+    }
+    match hours % 12 {
+        0 => (12, hours >= 12),
+        h => (h, hours >= 12),
+    }
+}
+
+/// Converts a register hour value and PM flag back to 0 to 23 hours
+fn hours_from_register(hours: u32, pm: bool, h12: bool) -> u32 {
+    if !h12 {
+        return hours;
+    }
+    hours % 12 + if pm { 12 } else { 0 }
+}
+
 fn bcd2_encode(word: u32) -> (u8, u8) {
     let mut value = word as u8;
     let mut bcd_high: u8 = 0;
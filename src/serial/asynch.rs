@@ -0,0 +1,245 @@
+//! Async serial, driven by the USART interrupts
+//!
+//! `Rx`, `Tx` and `Serial` implement the `embedded-io-async` `Read` and `Write` traits
+//! for 8 bit words. A future that can't make progress parks its waker and enables the
+//! RXNE, TXE or TC interrupt. The USART interrupt handler has to call `on_interrupt`,
+//! which masks the interrupt again and wakes the waiting task:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn USART1() {
+//!     Serial::<USART1>::on_interrupt();
+//! }
+//! ```
+//!
+//! The FIFO is not used by these futures, every interrupt moves the task forward by
+//! at least one byte.
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use cortex_m::interrupt::{self, Mutex};
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+
+use crate::serial::usart::{Error, Rx, Serial, Tx};
+use crate::stm32::*;
+
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+/// Waker of the task waiting on one direction of a USART
+struct WakerCell(Mutex<RefCell<Option<Waker>>>);
+
+impl WakerCell {
+    const fn new() -> Self {
+        WakerCell(Mutex::new(RefCell::new(None)))
+    }
+
+    fn register(&self, waker: &Waker) {
+        interrupt::free(|cs| {
+            let mut slot = self.0.borrow(cs).borrow_mut();
+            match slot.as_ref() {
+                Some(old) if old.will_wake(waker) => {}
+                _ => *slot = Some(waker.clone()),
+            }
+        });
+    }
+
+    fn wake(&self) {
+        if let Some(waker) = interrupt::free(|cs| self.0.borrow(cs).take()) {
+            waker.wake();
+        }
+    }
+}
+
+/// Reads at least one byte, parking the task until RXNE if none is available
+async fn read<S>(
+    serial: &mut S,
+    buf: &mut [u8],
+    waker: &WakerCell,
+    listen: fn(),
+) -> Result<usize, Error>
+where
+    S: hal::serial::Read<u8, Error = Error>,
+{
+    if buf.is_empty() {
+        return Ok(0);
+    }
+    poll_fn(|cx| {
+        let mut len = 0;
+        while len < buf.len() {
+            match serial.read() {
+                Ok(byte) => {
+                    buf[len] = byte;
+                    len += 1;
+                }
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(err)) => return Poll::Ready(Err(err)),
+            }
+        }
+        if len > 0 {
+            return Poll::Ready(Ok(len));
+        }
+        // A byte received after the check raises the interrupt right away
+        waker.register(cx.waker());
+        listen();
+        Poll::Pending
+    })
+    .await
+}
+
+/// Writes at least one byte, parking the task until TXE if the register is full
+async fn write<S>(
+    serial: &mut S,
+    buf: &[u8],
+    waker: &WakerCell,
+    listen: fn(),
+) -> Result<usize, Error>
+where
+    S: hal::serial::Write<u8, Error = Error>,
+{
+    if buf.is_empty() {
+        return Ok(0);
+    }
+    poll_fn(|cx| {
+        let mut len = 0;
+        while len < buf.len() {
+            match serial.write(buf[len]) {
+                Ok(()) => len += 1,
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(err)) => return Poll::Ready(Err(err)),
+            }
+        }
+        if len > 0 {
+            return Poll::Ready(Ok(len));
+        }
+        waker.register(cx.waker());
+        listen();
+        Poll::Pending
+    })
+    .await
+}
+
+/// Waits for the transmission complete flag
+async fn flush<S>(serial: &mut S, waker: &WakerCell, listen: fn()) -> Result<(), Error>
+where
+    S: hal::serial::Write<u8, Error = Error>,
+{
+    poll_fn(|cx| match serial.flush() {
+        Ok(()) => Poll::Ready(Ok(())),
+        Err(nb::Error::WouldBlock) => {
+            waker.register(cx.waker());
+            listen();
+            Poll::Pending
+        }
+        Err(nb::Error::Other(err)) => Poll::Ready(Err(err)),
+    })
+    .await
+}
+
+macro_rules! uart_async {
+    ($($USARTX:ident: $usartX:ident,)+) => {
+        $(
+            mod $usartX {
+                use crate::stm32::$USARTX;
+
+                pub(super) static RX: super::WakerCell = super::WakerCell::new();
+                pub(super) static TX: super::WakerCell = super::WakerCell::new();
+
+                pub(super) fn listen_rxne() {
+                    let usart = unsafe { &(*$USARTX::ptr()) };
+                    usart.cr1_disabled().modify(|_, w| w.rxneie().set_bit());
+                }
+
+                pub(super) fn listen_txe() {
+                    let usart = unsafe { &(*$USARTX::ptr()) };
+                    usart.cr1_disabled().modify(|_, w| w.txeie().set_bit());
+                }
+
+                pub(super) fn listen_tc() {
+                    let usart = unsafe { &(*$USARTX::ptr()) };
+                    usart.cr1_disabled().modify(|_, w| w.tcie().set_bit());
+                }
+            }
+
+            impl Serial<$USARTX> {
+                /// Wakes the tasks waiting on the USART, call it from the USART interrupt
+                ///
+                /// Only the interrupts enabled by a pending future are handled, they are
+                /// masked until the future is polled again.
+                pub fn on_interrupt() {
+                    let usart = unsafe { &(*$USARTX::ptr()) };
+                    let cr1 = usart.cr1_disabled().read();
+                    let isr = usart.isr_disabled().read();
+
+                    if cr1.rxneie().bit_is_set()
+                        && (isr.rxne().bit_is_set() || isr.ore().bit_is_set())
+                    {
+                        usart.cr1_disabled().modify(|_, w| w.rxneie().clear_bit());
+                        $usartX::RX.wake();
+                    }
+                    if (cr1.txeie().bit_is_set() && isr.txe().bit_is_set())
+                        || (cr1.tcie().bit_is_set() && isr.tc().bit_is_set())
+                    {
+                        usart
+                            .cr1_disabled()
+                            .modify(|_, w| w.txeie().clear_bit().tcie().clear_bit());
+                        $usartX::TX.wake();
+                    }
+                }
+            }
+
+            impl ErrorType for Rx<$USARTX> {
+                type Error = Error;
+            }
+
+            impl Read for Rx<$USARTX> {
+                async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+                    read(self, buf, &$usartX::RX, $usartX::listen_rxne).await
+                }
+            }
+
+            impl ErrorType for Tx<$USARTX> {
+                type Error = Error;
+            }
+
+            impl Write for Tx<$USARTX> {
+                async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+                    write(self, buf, &$usartX::TX, $usartX::listen_txe).await
+                }
+
+                async fn flush(&mut self) -> Result<(), Error> {
+                    flush(self, &$usartX::TX, $usartX::listen_tc).await
+                }
+            }
+
+            impl ErrorType for Serial<$USARTX> {
+                type Error = Error;
+            }
+
+            impl Read for Serial<$USARTX> {
+                async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+                    read(self, buf, &$usartX::RX, $usartX::listen_rxne).await
+                }
+            }
+
+            impl Write for Serial<$USARTX> {
+                async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+                    write(self, buf, &$usartX::TX, $usartX::listen_txe).await
+                }
+
+                async fn flush(&mut self) -> Result<(), Error> {
+                    flush(self, &$usartX::TX, $usartX::listen_tc).await
+                }
+            }
+        )+
+    }
+}
+
+uart_async! {
+    USART1: usart1,
+    USART2: usart2,
+}
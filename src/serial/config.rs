@@ -1,5 +1,5 @@
 use crate::prelude::*;
-use crate::time::Bps;
+use crate::time::{Bps, Hertz};
 
 #[derive(Eq, PartialEq, PartialOrd, Clone, Copy)]
 pub enum WordLength {
@@ -15,6 +15,73 @@ pub enum Parity {
     ParityOdd,
 }
 
+/// IrDA SIR ENDEC mode
+#[derive(Eq, PartialEq, PartialOrd, Clone, Copy, Debug)]
+pub enum IrdaMode {
+    /// Normal mode, pulses of 3/16 bit period
+    Normal,
+    /// Low-power mode, pulses derived from a ~1.8432 MHz prescaled clock
+    LowPower,
+}
+
+/// Method used to leave mute mode
+#[derive(Eq, PartialEq, PartialOrd, Clone, Copy, Debug)]
+pub enum WakeupMethod {
+    /// Wake up on an idle line
+    IdleLine,
+    /// Wake up on a frame carrying the node address in its MSB marked frame
+    AddressMark,
+}
+
+/// ISO 7816-3 smartcard mode settings, uses builder pattern
+///
+/// On a parity error the receiver pulls the line low during the stop bit (NACK) if
+/// `nack` is set, and reports `Error::Parity`. The transmitter repeats a NACKed frame
+/// up to `retries` times before it reports `Error::Framing`.
+#[derive(Eq, PartialEq, PartialOrd, Clone, Copy, Debug)]
+pub struct SmartcardConfig {
+    pub(crate) card_clock: Hertz,
+    pub(crate) guard_time: u8,
+    pub(crate) retries: u8,
+    pub(crate) nack: bool,
+}
+
+impl SmartcardConfig {
+    /// `card_clock` is the clock output on CK, derived from the USART clock in steps
+    /// of two
+    pub fn new(card_clock: Hertz) -> Self {
+        SmartcardConfig {
+            card_clock,
+            guard_time: 0,
+            retries: 3,
+            nack: true,
+        }
+    }
+
+    /// Extra guard time after each transmitted frame, in bit periods
+    pub fn guard_time(mut self, guard_time: u8) -> Self {
+        self.guard_time = guard_time;
+        self
+    }
+
+    /// Number of transmit retries after a NACK, in range 0 .. 7
+    pub fn retries(mut self, retries: u8) -> Self {
+        self.retries = retries & 0x07;
+        self
+    }
+
+    /// Send a NACK when a received frame has a parity error
+    pub fn nack(mut self, nack: bool) -> Self {
+        self.nack = nack;
+        self
+    }
+}
+
+/// Number of stop bits
+///
+/// 0.5 and 1.5 stop bits are intended for smartcard mode (0.5 when receiving, 1.5 in
+/// both directions). IrDA mode only supports 1 stop bit, other values are rejected
+/// when the serial port is created.
 #[derive(Eq, PartialEq, PartialOrd, Clone, Copy, Debug)]
 pub enum StopBits {
     #[doc = "1 stop bit"]
@@ -69,6 +136,11 @@ pub struct Config {
     pub(crate) rx_fifo_interrupt: bool,
     #[doc = "Number of bits no activity on rx line"]
     pub(crate) receiver_timeout: Option<u32>,
+    pub(crate) wakeup_method: Option<WakeupMethod>,
+    pub(crate) address: u8,
+    pub(crate) address_7bit: bool,
+    pub(crate) irda: Option<IrdaMode>,
+    pub(crate) smartcard: Option<SmartcardConfig>,
 }
 
 impl Config {
@@ -140,6 +212,43 @@ impl Config {
         self
     }
 
+    /// Enable mute mode, the receiver leaves mute mode with the given method
+    pub fn mute_mode(mut self, method: WakeupMethod) -> Self {
+        self.wakeup_method = Some(method);
+        self
+    }
+
+    /// Node address as 4 bit address in range 0 .. 15
+    pub fn node_address(mut self, address: u8) -> Self {
+        self.address = address & 0x0f;
+        self.address_7bit = false;
+        self
+    }
+
+    /// Node address as 7 bit address in range 0 .. 127
+    pub fn node_address_7bit(mut self, address: u8) -> Self {
+        self.address = address & 0x7f;
+        self.address_7bit = true;
+        self
+    }
+
+    /// Enable the IrDA SIR ENDEC. IrDA requires 1 stop bit and a baudrate up to 115200.
+    pub fn irda(mut self, mode: IrdaMode) -> Self {
+        self.irda = Some(mode);
+        self
+    }
+
+    /// Enable smartcard mode with the 8 data bits, even parity and 1.5 stop bits framing
+    /// it requires. The card clock is output on the RTS/DE/CK pin, pass it as the third
+    /// pin and the card I/O line as an open drain TX pin.
+    pub fn smartcard(mut self, smartcard: SmartcardConfig) -> Self {
+        self.wordlength = WordLength::DataBits9;
+        self.parity = Parity::ParityEven;
+        self.stopbits = StopBits::STOP1P5;
+        self.smartcard = Some(smartcard);
+        self
+    }
+
     /// Configure receiver timout in microseconds. Call after baudrate is set.
     pub fn receiver_timeout_us(mut self, timeout_us: u32) -> Self {
         let t = timeout_us as u64 * self.baudrate.0 as u64 / 1_000_000u64;
@@ -166,6 +275,11 @@ impl Default for Config {
             tx_fifo_interrupt: false,
             rx_fifo_interrupt: false,
             receiver_timeout: None,
+            wakeup_method: None,
+            address: 0,
+            address_7bit: false,
+            irda: None,
+            smartcard: None,
         }
     }
 }
@@ -1,3 +1,5 @@
+#[cfg(feature = "async")]
+pub mod asynch;
 pub mod config;
 pub mod usart;
 
@@ -1,3 +1,4 @@
+use core::cmp;
 use core::fmt;
 use core::marker::PhantomData;
 
@@ -7,6 +8,7 @@ use crate::rcc::*;
 use crate::serial;
 use crate::serial::config::*;
 use crate::stm32::*;
+use crate::time::Bps;
 
 use nb::block;
 
@@ -35,6 +37,12 @@ pub enum Event {
     /// TXFIFO empty
     TXFE = 1 << 23,
 
+    /// Receiver is in mute mode
+    RWU = 1 << 19,
+
+    /// Character match, the received character equals the node address
+    CMF = 1 << 17,
+
     /// Active when a communication is ongoing on the RX line
     BUSY = 1 << 16,
 
@@ -70,31 +78,148 @@ impl Event {
     }
 }
 
+/// Snapshot of the interrupt and status register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    pub tx_fifo_threshold: bool,
+    pub rx_fifo_threshold: bool,
+    pub rx_fifo_full: bool,
+    pub tx_fifo_empty: bool,
+    pub busy: bool,
+    pub receiver_timeout: bool,
+    /// TX data register or FIFO not full
+    pub txe: bool,
+    pub transmission_complete: bool,
+    /// RX data register or FIFO not empty
+    pub rxne: bool,
+    pub idle: bool,
+    pub overrun: bool,
+    pub noise: bool,
+    pub framing: bool,
+    pub parity: bool,
+}
+
+impl Status {
+    fn from_bits(isr: u32) -> Self {
+        let bit = |event: Event| isr & event.val() != 0;
+        Status {
+            tx_fifo_threshold: bit(Event::TXFT),
+            rx_fifo_threshold: bit(Event::RXFT),
+            rx_fifo_full: bit(Event::RXFF),
+            tx_fifo_empty: bit(Event::TXFE),
+            busy: bit(Event::BUSY),
+            receiver_timeout: bit(Event::RTOF),
+            txe: bit(Event::Txe),
+            transmission_complete: bit(Event::TC),
+            rxne: bit(Event::Rxne),
+            idle: bit(Event::Idle),
+            overrun: bit(Event::ORE),
+            noise: bit(Event::NE),
+            framing: bit(Event::FE),
+            parity: bit(Event::PE),
+        }
+    }
+}
+
 /// Serial receiver
-pub struct Rx<USART> {
+pub struct Rx<USART, WORD = u8> {
     _usart: PhantomData<USART>,
+    _word: PhantomData<WORD>,
 }
 
 /// Serial transmitter
-pub struct Tx<USART> {
+pub struct Tx<USART, WORD = u8> {
     _usart: PhantomData<USART>,
+    _word: PhantomData<WORD>,
 }
 
 /// Serial abstraction
-pub struct Serial<USART> {
-    tx: Tx<USART>,
-    rx: Rx<USART>,
+///
+/// `WORD` is the data type of the read and write operations, use `u16` to
+/// transfer 9 bit words intact.
+pub struct Serial<USART, WORD = u8> {
+    tx: Tx<USART, WORD>,
+    rx: Rx<USART, WORD>,
     usart: USART,
 }
 
+impl<USART> Serial<USART, u8> {
+    /// Converts this Serial into a version that reads and writes `u16` words,
+    /// to be used with a 9 bit word length
+    pub fn with_u16_data(self) -> Serial<USART, u16> {
+        Serial {
+            tx: self.tx.with_u16_data(),
+            rx: self.rx.with_u16_data(),
+            usart: self.usart,
+        }
+    }
+}
+
+impl<USART> Serial<USART, u16> {
+    /// Converts this Serial into a version that reads and writes `u8` words
+    pub fn with_u8_data(self) -> Serial<USART, u8> {
+        Serial {
+            tx: self.tx.with_u8_data(),
+            rx: self.rx.with_u8_data(),
+            usart: self.usart,
+        }
+    }
+}
+
+impl<USART> Rx<USART, u8> {
+    /// Converts this Rx into a version that reads `u16` words
+    pub fn with_u16_data(self) -> Rx<USART, u16> {
+        Rx {
+            _usart: PhantomData,
+            _word: PhantomData,
+        }
+    }
+}
+
+impl<USART> Rx<USART, u16> {
+    /// Converts this Rx into a version that reads `u8` words
+    pub fn with_u8_data(self) -> Rx<USART, u8> {
+        Rx {
+            _usart: PhantomData,
+            _word: PhantomData,
+        }
+    }
+}
+
+impl<USART> Tx<USART, u8> {
+    /// Converts this Tx into a version that writes `u16` words
+    pub fn with_u16_data(self) -> Tx<USART, u16> {
+        Tx {
+            _usart: PhantomData,
+            _word: PhantomData,
+        }
+    }
+}
+
+impl<USART> Tx<USART, u16> {
+    /// Converts this Tx into a version that writes `u8` words
+    pub fn with_u8_data(self) -> Tx<USART, u8> {
+        Tx {
+            _usart: PhantomData,
+            _word: PhantomData,
+        }
+    }
+}
+
 // Serial TX pin
 pub trait TxPin<USART> {
+    /// False for the `NoTx` filler, the transmitter is then left disabled
+    const ENABLED: bool = true;
+
     fn setup(&self);
     fn release(self) -> Self;
 }
 
 // Serial RX pin
 pub trait RxPin<USART> {
+    /// False for the `NoRx` filler, the receiver is then left disabled
+    const ENABLED: bool = true;
+
     fn setup(&self);
     fn release(self) -> Self;
 }
@@ -102,6 +227,8 @@ pub trait RxPin<USART> {
 pub struct NoTx;
 
 impl<USART> TxPin<USART> for NoTx {
+    const ENABLED: bool = false;
+
     fn setup(&self) {}
 
     fn release(self) -> Self {
@@ -111,6 +238,8 @@ impl<USART> TxPin<USART> for NoTx {
 pub struct NoRx;
 
 impl<USART> RxPin<USART> for NoRx {
+    const ENABLED: bool = false;
+
     fn setup(&self) {}
 
     fn release(self) -> Self {
@@ -127,6 +256,8 @@ pub trait DriverEnablePin<USART> {
 // Serial pins
 pub trait Pins<USART> {
     const DRIVER_ENABLE: bool;
+    const TX_ENABLE: bool;
+    const RX_ENABLE: bool;
 
     fn setup(&self);
     fn release(self) -> Self;
@@ -139,6 +270,8 @@ where
     RX: RxPin<USART>,
 {
     const DRIVER_ENABLE: bool = false;
+    const TX_ENABLE: bool = TX::ENABLED;
+    const RX_ENABLE: bool = RX::ENABLED;
 
     fn setup(&self) {
         self.0.setup();
@@ -158,6 +291,8 @@ where
     DE: DriverEnablePin<USART>,
 {
     const DRIVER_ENABLE: bool = true;
+    const TX_ENABLE: bool = TX::ENABLED;
+    const RX_ENABLE: bool = RX::ENABLED;
 
     fn setup(&self) {
         self.0.setup();
@@ -241,7 +376,7 @@ macro_rules! uart_shared {
             }
         )+
 
-        impl Rx<$USARTX> {
+        impl<WORD> Rx<$USARTX, WORD> {
             pub fn listen(&mut self) {
                 let usart = unsafe { &(*$USARTX::ptr()) };
                 usart.cr1_disabled().modify(|_, w| w.rxneie().set_bit());
@@ -258,12 +393,29 @@ macro_rules! uart_shared {
                 let usart = unsafe { &(*$USARTX::ptr()) };
                 usart.isr_disabled().read().rxne().bit_is_set()
             }
-        }
 
-        impl hal::serial::Read<u8> for Rx<$USARTX> {
-            type Error = Error;
+            /// Discards all received data and clears the overrun, framing, noise and
+            /// parity error flags
+            pub fn clear_rx_fifo(&mut self) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                while usart.isr_enabled().read().rxfne().bit_is_set() {
+                    let _ = usart.rdr().read();
+                }
+                usart.icr().write(|w| {
+                    w.orecf()
+                        .set_bit()
+                        .fecf()
+                        .set_bit()
+                        .necf()
+                        .set_bit()
+                        .pecf()
+                        .set_bit()
+                });
+            }
+        }
 
-            fn read(&mut self) -> nb::Result<u8, Error> {
+        impl<WORD> Rx<$USARTX, WORD> {
+            fn read_word(&mut self) -> nb::Result<u16, Error> {
                 let usart = unsafe { &(*$USARTX::ptr()) };
                 let isr = usart.isr_enabled().read();
 
@@ -281,23 +433,68 @@ macro_rules! uart_shared {
                         usart.icr().write(|w| w.orecf().set_bit());
                         nb::Error::Other(Error::Overrun)
                     } else if isr.rxfne().bit_is_set() {
-                        return Ok(usart.rdr().read().bits() as u8)
+                        return Ok(usart.rdr().read().bits() as u16)
                     } else {
                         nb::Error::WouldBlock
                     }
                 )
             }
+
+            /// Reads the next word even if it was received with an error
+            ///
+            /// Unlike `read`, a parity, framing or noise error doesn't discard the word, it is
+            /// returned together with the status flags seen when it was read, so the protocol
+            /// can decide what to do with it. The error flags are cleared afterwards. Returns
+            /// `None` while no word is available.
+            pub fn read_raw(&mut self) -> Option<(u16, Status)> {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                let status = Status::from_bits(usart.isr_enabled().read().bits());
+                if !status.rxne {
+                    return None;
+                }
+                let word = usart.rdr().read().bits() as u16;
+                usart.icr().write(|w| {
+                    w.orecf()
+                        .set_bit()
+                        .fecf()
+                        .set_bit()
+                        .necf()
+                        .set_bit()
+                        .pecf()
+                        .set_bit()
+                });
+                Some((word, status))
+            }
         }
 
-        impl hal::serial::Read<u8> for Serial<$USARTX> {
+        impl hal::serial::Read<u8> for Rx<$USARTX, u8> {
             type Error = Error;
 
             fn read(&mut self) -> nb::Result<u8, Error> {
+                self.read_word().map(|word| word as u8)
+            }
+        }
+
+        impl hal::serial::Read<u16> for Rx<$USARTX, u16> {
+            type Error = Error;
+
+            fn read(&mut self) -> nb::Result<u16, Error> {
+                self.read_word()
+            }
+        }
+
+        impl<WORD> hal::serial::Read<WORD> for Serial<$USARTX, WORD>
+        where
+            Rx<$USARTX, WORD>: hal::serial::Read<WORD, Error = Error>,
+        {
+            type Error = Error;
+
+            fn read(&mut self) -> nb::Result<WORD, Error> {
                 self.rx.read()
             }
         }
 
-        impl Tx<$USARTX> {
+        impl<WORD> Tx<$USARTX, WORD> {
             /// Starts listening for an interrupt event
             pub fn listen(&mut self) {
                 let usart = unsafe { &(*$USARTX::ptr()) };
@@ -317,10 +514,8 @@ macro_rules! uart_shared {
             }
         }
 
-        impl hal::serial::Write<u8> for Tx<$USARTX> {
-            type Error = Error;
-
-            fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        impl<WORD> Tx<$USARTX, WORD> {
+            fn flush_word(&mut self) -> nb::Result<(), Error> {
                 let usart = unsafe { &(*$USARTX::ptr()) };
                 if usart.isr_disabled().read().tc().bit_is_set() {
                     Ok(())
@@ -329,10 +524,10 @@ macro_rules! uart_shared {
                 }
             }
 
-            fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+            fn write_word(&mut self, word: u16) -> nb::Result<(), Error> {
                 let usart = unsafe { &(*$USARTX::ptr()) };
                 if usart.isr_disabled().read().txe().bit_is_set() {
-                    usart.tdr().write(|w| unsafe { w.bits(byte as u32) });
+                    usart.tdr().write(|w| unsafe { w.bits(word as u32) });
                     Ok(())
                 } else {
                     Err(nb::Error::WouldBlock)
@@ -340,23 +535,50 @@ macro_rules! uart_shared {
             }
         }
 
-        impl hal::serial::Write<u8> for Serial<$USARTX> {
+        impl hal::serial::Write<u8> for Tx<$USARTX, u8> {
             type Error = Error;
 
             fn flush(&mut self) -> nb::Result<(), Self::Error> {
-                self.tx.flush()
+                self.flush_word()
             }
 
             fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
-                self.tx.write(byte)
+                self.write_word(byte as u16)
             }
         }
 
-        impl Serial<$USARTX> {
+        impl hal::serial::Write<u16> for Tx<$USARTX, u16> {
+            type Error = Error;
+
+            fn flush(&mut self) -> nb::Result<(), Self::Error> {
+                self.flush_word()
+            }
+
+            fn write(&mut self, word: u16) -> nb::Result<(), Self::Error> {
+                self.write_word(word)
+            }
+        }
+
+        impl<WORD> hal::serial::Write<WORD> for Serial<$USARTX, WORD>
+        where
+            Tx<$USARTX, WORD>: hal::serial::Write<WORD, Error = Error>,
+        {
+            type Error = Error;
+
+            fn flush(&mut self) -> nb::Result<(), Self::Error> {
+                self.tx.flush()
+            }
+
+            fn write(&mut self, word: WORD) -> nb::Result<(), Self::Error> {
+                self.tx.write(word)
+            }
+        }
+
+        impl<WORD> Serial<$USARTX, WORD> {
 
             /// Separates the serial struct into separate channel objects for sending (Tx) and
             /// receiving (Rx)
-            pub fn split(self) -> (Tx<$USARTX>, Rx<$USARTX>) {
+            pub fn split(self) -> (Tx<$USARTX, WORD>, Rx<$USARTX, WORD>) {
                 (self.tx, self.rx)
             }
 
@@ -386,6 +608,20 @@ macro_rules! uart {
                 config: serial::Config,
                 rcc: &mut Rcc,
             ) -> Result<Self, InvalidConfig> {
+                if config.irda.is_some()
+                    && (config.stopbits != StopBits::STOP1 || config.baudrate.0 > 115_200)
+                {
+                    return Err(InvalidConfig);
+                }
+
+                if config.smartcard.is_some()
+                    && (config.irda.is_some()
+                        || config.wordlength != WordLength::DataBits9
+                        || config.parity != Parity::ParityEven)
+                {
+                    return Err(InvalidConfig);
+                }
+
                 // Enable clock for USART
                 $USARTX::enable(rcc);
 
@@ -404,6 +640,10 @@ macro_rules! uart {
                         .bits(config.stopbits.bits())
                         .swap()
                         .bit(config.swap)
+                        .add()
+                        .bits(config.address)
+                        .addm7()
+                        .bit(config.address_7bit)
                 });
 
                 if let Some(timeout) = config.receiver_timeout {
@@ -423,26 +663,69 @@ macro_rules! uart {
                         .bit(config.rx_fifo_interrupt)
                 });
 
+                if let Some(mode) = config.irda {
+                    // Normal mode requires a prescaler of 1, low-power mode divides down to ~1.8432 MHz
+                    let psc = match mode {
+                        IrdaMode::Normal => 1,
+                        IrdaMode::LowPower => (clk / 1_843_200).clamp(1, 255),
+                    };
+                    usart.gtpr().modify(|_, w| unsafe { w.psc().bits(psc as u8) });
+                    usart.cr3().modify(|_, w| {
+                        w.iren()
+                            .set_bit()
+                            .irlp()
+                            .bit(mode == IrdaMode::LowPower)
+                    });
+                }
+
+                if let Some(smartcard) = config.smartcard {
+                    // CK runs at the USART clock divided by twice the prescaler
+                    let psc = (clk / (2 * smartcard.card_clock.raw() as u64)).clamp(1, 31);
+                    usart.gtpr().write(|w| unsafe {
+                        w.psc()
+                            .bits(psc as u8)
+                            .gt()
+                            .bits(smartcard.guard_time)
+                    });
+                    usart.cr2().modify(|_, w| w.clken().set_bit());
+                    usart.cr3().modify(|_, w| unsafe {
+                        w.scen()
+                            .set_bit()
+                            .nack()
+                            .bit(smartcard.nack)
+                            .scarcnt()
+                            .bits(smartcard.retries)
+                    });
+                }
+
                 usart.cr1_enabled().modify(|_, w| {
                     w.ue()
                         .set_bit()
                         .te()
-                        .set_bit()
+                        .bit(PINS::TX_ENABLE)
                         .re()
-                        .set_bit()
+                        .bit(PINS::RX_ENABLE)
                         .m0()
-                        .bit(config.wordlength == WordLength::DataBits7)
-                        .m1()
                         .bit(config.wordlength == WordLength::DataBits9)
+                        .m1()
+                        .bit(config.wordlength == WordLength::DataBits7)
                         .pce()
                         .bit(config.parity != Parity::ParityNone)
                         .ps()
                         .bit(config.parity == Parity::ParityOdd)
                         .fifoen()
                         .bit(config.fifo_enable)
+                        .mme()
+                        .bit(config.wakeup_method.is_some())
+                        .wake()
+                        .bit(config.wakeup_method == Some(WakeupMethod::AddressMark))
                 });
 
-                usart.cr3().write(|w| w.dem().bit(PINS::DRIVER_ENABLE));
+                // In smartcard mode the third pin carries CK instead of DE
+                usart.cr3().modify(|_, w| {
+                    w.dem()
+                        .bit(PINS::DRIVER_ENABLE && config.smartcard.is_none())
+                });
 
                 // Enable pins
                 pins.setup();
@@ -450,14 +733,18 @@ macro_rules! uart {
                 Ok(Serial {
                     tx: Tx {
                         _usart: PhantomData,
+                        _word: PhantomData,
                     },
                     rx: Rx {
                         _usart: PhantomData,
+                        _word: PhantomData,
                     },
                     usart,
                 })
             }
+        }
 
+        impl<WORD> Serial<$USARTX, WORD> {
             /// Starts listening for an interrupt event
             pub fn listen(&mut self, event: Event) {
                 match event {
@@ -470,6 +757,7 @@ macro_rules! uart {
                         .usart
                         .cr1_disabled()
                         .modify(|_, w| w.idleie().set_bit()),
+                    Event::CMF => self.usart.cr1_disabled().modify(|_, w| w.cmie().set_bit()),
                     _ => {}
                 }
             }
@@ -489,10 +777,50 @@ macro_rules! uart {
                         .usart
                         .cr1_disabled()
                         .modify(|_, w| w.idleie().clear_bit()),
+                    Event::CMF => self
+                        .usart
+                        .cr1_disabled()
+                        .modify(|_, w| w.cmie().clear_bit()),
                     _ => {}
                 }
             }
 
+            /// Changes the baud rate, keeping the rest of the configuration
+            ///
+            /// BRR can only be written while the USART is disabled, so this waits for the
+            /// ongoing transmission to complete before clearing UE. A frame being received
+            /// at that moment is lost. Returns the baud rate actually achieved, a baud rate
+            /// of zero is rejected without touching the USART.
+            pub fn set_baudrate(&mut self, baudrate: Bps, rcc: &Rcc) -> Result<Bps, InvalidConfig> {
+                if baudrate.0 == 0 {
+                    return Err(InvalidConfig);
+                }
+                let clk = rcc.clocks.apb_clk.raw();
+                // BRR must be at least 16 with 16x oversampling
+                let div = cmp::max(clk / baudrate.0, 16);
+                // Wait until the TX FIFO and the shift register are empty before disabling
+                while self.usart.isr_enabled().read().tc().bit_is_clear() {}
+                self.usart.cr1_enabled().modify(|_, w| w.ue().clear_bit());
+                self.usart.brr().write(|w| unsafe { w.bits(div) });
+                self.usart.cr1_enabled().modify(|_, w| w.ue().set_bit());
+                Ok(Bps(clk / div))
+            }
+
+            /// Reads the next word along with its error flags, see [`Rx::read_raw`]
+            pub fn read_raw(&mut self) -> Option<(u16, Status)> {
+                self.rx.read_raw()
+            }
+
+            /// Returns the raw ISR word, e.g. to log all flags after a fault
+            pub fn isr_bits(&self) -> u32 {
+                self.usart.isr_enabled().read().bits()
+            }
+
+            /// Returns all the status flags at once
+            pub fn status(&self) -> Status {
+                Status::from_bits(self.isr_bits())
+            }
+
             /// Check if interrupt event is pending
             pub fn is_pending(&mut self, event: Event) -> bool {
                 (self.usart.isr_enabled().read().bits() & event.val()) != 0
@@ -508,7 +836,7 @@ macro_rules! uart {
             }
         }
 
-        impl Tx<$USARTX> {
+        impl<WORD> Tx<$USARTX, WORD> {
             /// Returns true if the tx fifo threshold has been reached.
             pub fn fifo_threshold_reached(&self) -> bool {
                 let usart = unsafe { &(*$USARTX::ptr()) };
@@ -516,7 +844,7 @@ macro_rules! uart {
             }
         }
 
-        impl Rx<$USARTX> {
+        impl<WORD> Rx<$USARTX, WORD> {
             /// Check if receiver timeout has lapsed
             /// Returns the current state of the ISR RTOF bit
             pub fn timeout_lapsed(&self) -> bool {
@@ -524,6 +852,20 @@ macro_rules! uart {
                 usart.isr_enabled().read().rtof().bit_is_set()
             }
 
+            /// Put the receiver in mute mode. Requires the mute mode to be configured.
+            /// With address mark wake up, the receiver is muted again by hardware when
+            /// a frame for another node address is received.
+            pub fn enter_mute(&mut self) {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                usart.rqr().write(|w| w.mmrq().set_bit());
+            }
+
+            /// Returns true if the receiver is in mute mode
+            pub fn is_muted(&self) -> bool {
+                let usart = unsafe { &(*$USARTX::ptr()) };
+                usart.isr_enabled().read().rwu().bit_is_set()
+            }
+
             /// Clear pending receiver timeout interrupt
             pub fn clear_timeout(&mut self) {
                 let usart = unsafe { &(*$USARTX::ptr()) };
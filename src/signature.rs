@@ -0,0 +1,36 @@
+//! Device electronic signature
+//!
+//! The unique device ID and the flash size are programmed in the system memory
+//! during production, see the "Device electronic signature" chapter of RM0490.
+use core::ptr;
+
+const UID_BASE: usize = 0x1fff_7550;
+const FLASH_SIZE_BASE: usize = 0x1fff_75a0;
+
+/// Returns the 96 bit unique device ID as three words, lowest address first
+pub fn uid() -> [u32; 3] {
+    let uid = UID_BASE as *const u32;
+    // NOTE(unsafe) read only system memory, always mapped
+    unsafe {
+        [
+            ptr::read_volatile(uid),
+            ptr::read_volatile(uid.add(1)),
+            ptr::read_volatile(uid.add(2)),
+        ]
+    }
+}
+
+/// Returns the unique device ID as bytes, in memory order
+pub fn uid_bytes() -> [u8; 12] {
+    let mut bytes = [0; 12];
+    for (chunk, word) in bytes.chunks_exact_mut(4).zip(uid().iter()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    bytes
+}
+
+/// Returns the flash memory size in kilobytes
+pub fn flash_size_kb() -> u16 {
+    // NOTE(unsafe) read only system memory, always mapped
+    unsafe { ptr::read_volatile(FLASH_SIZE_BASE as *const u16) }
+}
@@ -2,7 +2,8 @@ use crate::gpio::*;
 use crate::rcc::*;
 use crate::stm32::SPI;
 use crate::time::Hertz;
-use core::ptr;
+use core::{cmp, ptr};
+use hal::spi::FullDuplex;
 pub use hal::spi::{Mode, Phase, Polarity, MODE_0, MODE_1, MODE_2, MODE_3};
 
 /// SPI error
@@ -16,6 +17,34 @@ pub enum Error {
     Crc,
 }
 
+impl hal1::spi::Error for Error {
+    fn kind(&self) -> hal1::spi::ErrorKind {
+        match self {
+            Error::Overrun => hal1::spi::ErrorKind::Overrun,
+            Error::ModeFault => hal1::spi::ErrorKind::ModeFault,
+            Error::Crc => hal1::spi::ErrorKind::Other,
+        }
+    }
+}
+
+/// Frame format
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FrameFormat {
+    /// Motorola SPI, the default
+    Motorola,
+    /// TI synchronous serial, NSS is driven by hardware as a frame sync pulse
+    TI,
+}
+
+/// Receive FIFO level that sets RXNE
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RxFifoThreshold {
+    /// RXNE is set once two bytes are available, for 16-bit reads of the data register
+    Half,
+    /// RXNE is set once one byte is available, the default
+    Quarter,
+}
+
 /// A filler type for when the SCK pin is unnecessary
 pub struct NoSck;
 /// A filler type for when the Miso pin is unnecessary
@@ -43,6 +72,12 @@ pub trait PinMosi<SPI> {
     fn release(self) -> Self;
 }
 
+/// Hardware NSS pin, only used as frame sync in TI mode
+pub trait PinNss<SPI> {
+    fn setup(&self);
+    fn release(self) -> Self;
+}
+
 impl<SPI, SCK, MISO, MOSI> Pins<SPI> for (SCK, MISO, MOSI)
 where
     SCK: PinSck<SPI>,
@@ -64,6 +99,133 @@ where
 pub struct Spi<SPI, PINS> {
     spi: SPI,
     pins: PINS,
+    clk: Hertz,
+}
+
+/// BR setting for the fastest SCK not above `speed`
+fn baud_rate_divider(clk: Hertz, speed: Hertz) -> u8 {
+    match clk / speed {
+        0 => unreachable!(),
+        1..=2 => 0b000,
+        3..=5 => 0b001,
+        6..=11 => 0b010,
+        12..=23 => 0b011,
+        24..=47 => 0b100,
+        48..=95 => 0b101,
+        96..=191 => 0b110,
+        _ => 0b111,
+    }
+}
+
+/// embedded-hal 1.0 `SpiDevice` on a bus, toggling a CS pin around every transaction
+///
+/// `BUS` is either an owned `Spi` or a `&mut Spi`, so several devices on one bus can
+/// be built for each transaction from the same mutable borrow. The delays are in
+/// nanoseconds and default to zero.
+pub struct SpiDevice<BUS, CS, DELAY> {
+    bus: BUS,
+    cs: CS,
+    delay: DELAY,
+    cs_setup_ns: u32,
+    cs_hold_ns: u32,
+    cs_idle_ns: u32,
+}
+
+impl<BUS, CS, DELAY> SpiDevice<BUS, CS, DELAY>
+where
+    CS: hal1::digital::OutputPin<Error = core::convert::Infallible>,
+{
+    /// Deasserts (drives high) the CS pin
+    pub fn new(bus: BUS, mut cs: CS, delay: DELAY) -> Self {
+        cs.set_high().ok();
+        SpiDevice {
+            bus,
+            cs,
+            delay,
+            cs_setup_ns: 0,
+            cs_hold_ns: 0,
+            cs_idle_ns: 0,
+        }
+    }
+
+    /// Delay between asserting CS and the first clock edge
+    pub fn cs_setup(mut self, ns: u32) -> Self {
+        self.cs_setup_ns = ns;
+        self
+    }
+
+    /// Delay between the last clock edge and deasserting CS
+    pub fn cs_hold(mut self, ns: u32) -> Self {
+        self.cs_hold_ns = ns;
+        self
+    }
+
+    /// Minimum time CS stays deasserted between two transactions
+    pub fn cs_idle(mut self, ns: u32) -> Self {
+        self.cs_idle_ns = ns;
+        self
+    }
+
+    pub fn release(self) -> (BUS, CS, DELAY) {
+        (self.bus, self.cs, self.delay)
+    }
+}
+
+impl<BUS, CS, DELAY> hal1::spi::ErrorType for SpiDevice<BUS, CS, DELAY>
+where
+    BUS: hal1::spi::ErrorType,
+{
+    type Error = BUS::Error;
+}
+
+impl<BUS, CS, DELAY> hal1::spi::SpiDevice<u8> for SpiDevice<BUS, CS, DELAY>
+where
+    BUS: hal1::spi::SpiBus<u8>,
+    CS: hal1::digital::OutputPin<Error = core::convert::Infallible>,
+    DELAY: hal1::delay::DelayNs,
+{
+    fn transaction(
+        &mut self,
+        operations: &mut [hal1::spi::Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        use hal1::spi::Operation;
+
+        let SpiDevice {
+            bus,
+            cs,
+            delay,
+            cs_setup_ns,
+            cs_hold_ns,
+            cs_idle_ns,
+        } = self;
+        cs.set_low().ok();
+        if *cs_setup_ns > 0 {
+            delay.delay_ns(*cs_setup_ns);
+        }
+
+        let result = operations.iter_mut().try_for_each(|op| match op {
+            Operation::Read(words) => bus.read(words),
+            Operation::Write(words) => bus.write(words),
+            Operation::Transfer(read, write) => bus.transfer(read, write),
+            Operation::TransferInPlace(words) => bus.transfer_in_place(words),
+            Operation::DelayNs(ns) => {
+                bus.flush()?;
+                delay.delay_ns(*ns);
+                Ok(())
+            }
+        });
+        let flushed = bus.flush();
+
+        if *cs_hold_ns > 0 {
+            delay.delay_ns(*cs_hold_ns);
+        }
+        cs.set_high().ok();
+        if *cs_idle_ns > 0 {
+            delay.delay_ns(*cs_idle_ns);
+        }
+
+        result.and(flushed)
+    }
 }
 
 pub trait SpiExt: Sized {
@@ -77,6 +239,7 @@ macro_rules! spi {
         sck: [ $(($SCK:ty, $SCK_AF:expr),)+ ],
         miso: [ $(($MISO:ty, $MISO_AF:expr),)+ ],
         mosi: [ $(($MOSI:ty, $MOSI_AF:expr),)+ ],
+        nss: [ $(($NSS:ty, $NSS_AF:expr),)+ ],
     ) => {
         impl PinSck<$SPIX> for NoSck {
             fn setup(&self) {}
@@ -135,6 +298,17 @@ macro_rules! spi {
                 }
             }
         )*
+        $(
+            impl PinNss<$SPIX> for $NSS {
+                fn setup(&self) {
+                    self.set_alt_mode($NSS_AF);
+                }
+
+                fn release(self) -> Self {
+                    self.into_analog()
+                }
+            }
+        )*
 
         impl<PINS: Pins<$SPIX>> Spi<$SPIX, PINS> {
             pub fn $spiX(
@@ -150,17 +324,8 @@ macro_rules! spi {
                 // disable SS output
                 spi.cr2().write(|w| w.ssoe().clear_bit());
 
-                let br = match rcc.clocks.apb_clk / speed {
-                    0 => unreachable!(),
-                    1..=2 => 0b000,
-                    3..=5 => 0b001,
-                    6..=11 => 0b010,
-                    12..=23 => 0b011,
-                    24..=47 => 0b100,
-                    48..=95 => 0b101,
-                    96..=191 => 0b110,
-                    _ => 0b111,
-                };
+                let clk = rcc.clocks.apb_clk;
+                let br = baud_rate_divider(clk, speed);
 
                 spi.cr2().write(|w| unsafe {
                     w.frxth().set_bit().ds().bits(0b111).ssoe().clear_bit()
@@ -194,27 +359,218 @@ macro_rules! spi {
                         .set_bit()
                 });
 
-                Spi { spi, pins }
+                Spi { spi, pins, clk }
+            }
+
+            /// Changes the SCK frequency, the peripheral is disabled while BR is updated.
+            /// The prescaler is a power of two, check the actual rate with `frequency`.
+            pub fn set_frequency(&mut self, freq: Hertz) {
+                let br = baud_rate_divider(self.clk, freq);
+                while self.spi.sr().read().bsy().bit_is_set() {}
+                self.spi.cr1().modify(|_, w| w.spe().clear_bit());
+                self.spi.cr1().modify(|_, w| unsafe { w.br().bits(br) });
+                self.spi.cr1().modify(|_, w| w.spe().set_bit());
+            }
+
+            /// Selects the frame format. TI mode ignores CPOL, CPHA and software NSS and
+            /// needs an NSS pin set up with `PinNss::setup` for the frame sync output.
+            pub fn set_frame_format(&mut self, format: FrameFormat) {
+                while self.spi.sr().read().bsy().bit_is_set() {}
+                self.spi.cr1().modify(|_, w| w.spe().clear_bit());
+                self.spi.cr2().modify(|_, w| w.frf().bit(format == FrameFormat::TI));
+                self.spi.cr1().modify(|_, w| w.spe().set_bit());
             }
 
+            /// Address of the data register, for the peripheral address of a DMA channel.
+            /// Use byte sized accesses on both sides so no data packing is involved.
+            pub fn dr_address(&self) -> u32 {
+                self.spi.dr() as *const _ as u32
+            }
+
+            /// Lets a DMA channel on the `SPI1_TX` request feed the transmit FIFO, e.g. in
+            /// circular mode to refresh a display continuously
+            pub fn enable_tx_dma(&mut self) {
+                self.spi.cr2().modify(|_, w| w.txdmaen().set_bit());
+            }
+
+            /// Stops DMA transmission, call it after disabling the DMA channel. The FIFO is
+            /// drained and the last byte is clocked out before TXDMAEN is cleared, so the
+            /// DMA channel can be reconfigured right away.
+            pub fn stop_tx_dma(&mut self) {
+                while self.spi.sr().read().ftlvl().bits() != 0 {}
+                while self.spi.sr().read().bsy().bit_is_set() {}
+                self.spi.cr2().modify(|_, w| w.txdmaen().clear_bit());
+            }
+
+            /// Returns the actual SCK frequency
+            pub fn frequency(&self) -> Hertz {
+                let br = self.spi.cr1().read().br().bits();
+                Hertz::from_raw(self.clk.raw() >> (br + 1))
+            }
+
+            /// Returns true while a frame is being clocked or the transmit FIFO isn't empty
+            pub fn is_busy(&self) -> bool {
+                let sr = self.spi.sr().read();
+                sr.bsy().bit_is_set() || sr.ftlvl().bits() != 0
+            }
+
+            /// Sets the frame size, waits for the bus to become idle first
             pub fn data_size(&mut self, nr_bits: u8) {
+                while self.is_busy() {}
                 self.spi.cr2().modify(|_, w| unsafe {
                     w.ds().bits(nr_bits-1)
                 });
             }
 
+            /// Switches between full duplex and bidirectional mode, waits for the bus to
+            /// become idle first
             pub fn half_duplex_enable(&mut self, enable: bool) {
+                while self.is_busy() {}
                 self.spi.cr1().modify(|_, w|
                     w.bidimode().bit(enable)
                 );
             }
 
+            /// Selects the direction in bidirectional mode
+            ///
+            /// This doesn't wait on [`Self::is_busy`], as a master keeps clocking while
+            /// receiving. The caller has to make sure no transfer is in flight.
             pub fn half_duplex_output_enable(&mut self, enable: bool) {
                 self.spi.cr1().modify(|_, w|
                     w.bidioe().bit(enable)
                 );
             }
 
+            /// Clocks `max(read.len(), write.len())` bytes
+            ///
+            /// Zeros are sent once `write` is exhausted and received bytes beyond the length
+            /// of `read` are discarded. Every byte is read back before the next one is sent,
+            /// so the receive FIFO can't overrun during a long read phase. Unlike the
+            /// embedded-hal in-place `transfer` the read and write buffers are separate.
+            pub fn transfer_split(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+                for i in 0..cmp::max(read.len(), write.len()) {
+                    let byte = write.get(i).copied().unwrap_or(0);
+                    nb::block!(FullDuplex::send(self, byte))?;
+                    let byte = nb::block!(FullDuplex::read(self))?;
+                    if let Some(word) = read.get_mut(i) {
+                        *word = byte;
+                    }
+                }
+                Ok(())
+            }
+
+            /// Reads `buf.len()` bytes, sending 0x00 for each one
+            ///
+            /// Like `transfer_split` every byte is read back before the next one is sent, and
+            /// overrun or mode fault are reported as by the `FullDuplex` implementation.
+            pub fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+                self.transfer_split(buf, &[])
+            }
+
+            /// Receives `buf.len()` bytes in 3-wire half duplex mode
+            ///
+            /// Clearing `bidioe` starts the clock right away and it keeps running until `spe`
+            /// is cleared, so the peripheral is disabled one SPI clock after the second to last
+            /// byte arrived. Surplus bytes left in the FIFO are discarded and the bus is
+            /// switched back to output afterwards.
+            pub fn read_bidi(&mut self, buf: &mut [u8]) {
+                if buf.is_empty() {
+                    return;
+                }
+                let spi_clock = 2u32 << self.spi.cr1().read().br().bits();
+                let len = buf.len();
+
+                self.spi.cr1().modify(|_, w| w.spe().clear_bit());
+                self.flush_rx();
+                self.spi.cr1().modify(|_, w| w.bidimode().set_bit().bidioe().clear_bit());
+                self.spi.cr1().modify(|_, w| w.spe().set_bit());
+                if len == 1 {
+                    cortex_m::asm::delay(spi_clock);
+                    self.spi.cr1().modify(|_, w| w.spe().clear_bit());
+                }
+
+                for (i, word) in buf.iter_mut().enumerate() {
+                    while self.spi.sr().read().rxne().bit_is_clear() {}
+                    // NOTE(read_volatile) read only 1 byte
+                    *word = unsafe { ptr::read_volatile(self.spi.dr() as *const _ as *const u8) };
+                    if i + 2 == len {
+                        cortex_m::asm::delay(spi_clock);
+                        self.spi.cr1().modify(|_, w| w.spe().clear_bit());
+                    }
+                }
+
+                self.flush_rx();
+                self.spi.cr1().modify(|_, w| w.bidioe().set_bit());
+                self.spi.cr1().modify(|_, w| w.spe().set_bit());
+            }
+
+            /// Selects the receive FIFO level that sets RXNE, waits for the bus to become idle
+            /// first. The byte wise transfers of this driver need `Quarter`.
+            pub fn set_rx_fifo_threshold(&mut self, threshold: RxFifoThreshold) {
+                while self.is_busy() {}
+                self.spi.cr2().modify(|_, w| w.frxth().bit(threshold == RxFifoThreshold::Quarter));
+            }
+
+            /// Receives `buf.len()` bytes in receive only master mode, MOSI is not driven
+            ///
+            /// Setting `rxonly` starts the clock as soon as the peripheral is enabled and it keeps
+            /// running until `spe` is cleared. As with `read_bidi` the peripheral is disabled one
+            /// SPI clock after the second to last byte arrived, so the last byte is the final
+            /// one clocked. Surplus bytes left in the FIFO are discarded and full duplex mode is
+            /// restored afterwards.
+            pub fn read_rx_only(&mut self, buf: &mut [u8]) {
+                if buf.is_empty() {
+                    return;
+                }
+                let spi_clock = 2u32 << self.spi.cr1().read().br().bits();
+                let len = buf.len();
+
+                while self.is_busy() {}
+                self.spi.cr1().modify(|_, w| w.spe().clear_bit());
+                self.flush_rx();
+                self.spi.cr1().modify(|_, w| w.rxonly().set_bit());
+                self.spi.cr1().modify(|_, w| w.spe().set_bit());
+                if len == 1 {
+                    cortex_m::asm::delay(spi_clock);
+                    self.spi.cr1().modify(|_, w| w.spe().clear_bit());
+                }
+
+                for (i, word) in buf.iter_mut().enumerate() {
+                    while self.spi.sr().read().rxne().bit_is_clear() {}
+                    // NOTE(read_volatile) read only 1 byte
+                    *word = unsafe { ptr::read_volatile(self.spi.dr() as *const _ as *const u8) };
+                    if i + 2 == len {
+                        cortex_m::asm::delay(spi_clock);
+                        self.spi.cr1().modify(|_, w| w.spe().clear_bit());
+                    }
+                }
+
+                while self.spi.sr().read().bsy().bit_is_set() {}
+                self.flush_rx();
+                self.spi.cr1().modify(|_, w| w.rxonly().clear_bit());
+                self.spi.cr1().modify(|_, w| w.spe().set_bit());
+            }
+
+            /// Sends `buf` in 3-wire half duplex mode and waits until the bus is idle
+            pub fn write_bidi(&mut self, buf: &[u8]) {
+                self.spi.cr1().modify(|_, w| w.bidimode().set_bit().bidioe().set_bit());
+                self.spi.cr1().modify(|_, w| w.spe().set_bit());
+                for byte in buf {
+                    while self.spi.sr().read().txe().bit_is_clear() {}
+                    unsafe {
+                        self.spi.dr().write(|w| w.bits(*byte as _));
+                    }
+                }
+                while self.spi.sr().read().ftlvl().bits() != 0 {}
+                while self.spi.sr().read().bsy().bit_is_set() {}
+            }
+
+            fn flush_rx(&mut self) {
+                while self.spi.sr().read().frlvl().bits() != 0 {
+                    let _ = unsafe { ptr::read_volatile(self.spi.dr() as *const _ as *const u8) };
+                }
+            }
+
             pub fn release(self) -> ($SPIX, PINS) {
                 (self.spi, self.pins.release())
             }
@@ -245,7 +601,7 @@ macro_rules! spi {
                     // NOTE(read_volatile) read only 1 byte (the svd2rust API only allows
                     // reading a half-word)
                     return Ok(unsafe {
-                        ptr::read_volatile(&self.spi.dr() as *const _ as *const u8)
+                        ptr::read_volatile(self.spi.dr() as *const _ as *const u8)
                     });
                 } else {
                     nb::Error::WouldBlock
@@ -272,12 +628,46 @@ macro_rules! spi {
             }
         }
 
+        impl<PINS> hal1::spi::ErrorType for Spi<$SPIX, PINS> {
+            type Error = Error;
+        }
+
+        impl<PINS: Pins<$SPIX>> hal1::spi::SpiBus<u8> for Spi<$SPIX, PINS> {
+            fn read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+                Spi::transfer_split(self, words, &[])
+            }
+
+            fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+                Spi::transfer_split(self, &mut [], words)
+            }
+
+            fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+                Spi::transfer_split(self, read, write)
+            }
+
+            fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Error> {
+                for word in words.iter_mut() {
+                    nb::block!(FullDuplex::send(self, *word))?;
+                    *word = nb::block!(FullDuplex::read(self))?;
+                }
+                Ok(())
+            }
+
+            fn flush(&mut self) -> Result<(), Error> {
+                while self.spi.sr().read().ftlvl().bits() != 0 {}
+                while self.spi.sr().read().bsy().bit_is_set() {}
+                Ok(())
+            }
+        }
+
         impl<PINS> ::hal::blocking::spi::transfer::Default<u8> for Spi<$SPIX, PINS> {}
 
         impl<PINS> ::hal::blocking::spi::write::Default<u8> for Spi<$SPIX, PINS> {}
     }
 }
 
+// The C011 and C031 have a single SPI instance. The driver is generic over the
+// instance type already, another instance only needs its own `spi!` invocation.
 spi!(
     SPI,
     spi1,
@@ -300,4 +690,9 @@ spi!(
         (PB5<DefaultMode>, AltFunction::AF0),
         (PB6<DefaultMode>, AltFunction::AF8),
     ],
+    nss: [
+        (PA4<DefaultMode>, AltFunction::AF0),
+        (PA15<DefaultMode>, AltFunction::AF0),
+        (PB0<DefaultMode>, AltFunction::AF0),
+    ],
 );
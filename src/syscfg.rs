@@ -0,0 +1,84 @@
+//! System configuration controller
+//!
+//! On the C0 the EXTI port selection lives in the EXTI block itself (`EXTICRx`), it is
+//! set up by the GPIO `listen` methods and is not part of `SYSCFG`.
+use crate::rcc::{Enable, Rcc};
+use crate::stm32::SYSCFG;
+
+/// Memory mapped at address 0x0000_0000
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MemoryMode {
+    MainFlash = 0b00,
+    SystemFlash = 0b01,
+    Sram = 0b11,
+}
+
+/// I2C Fast-mode Plus driving capability, the value is the `CFGR1` bit position
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FastModePlus {
+    PB6 = 16,
+    PB7 = 17,
+    PB8 = 18,
+    PB9 = 19,
+    I2C1 = 20,
+    PA9 = 22,
+    PA10 = 23,
+}
+
+pub struct SysCfg {
+    rb: SYSCFG,
+}
+
+impl SysCfg {
+    pub fn new(syscfg: SYSCFG, rcc: &mut Rcc) -> Self {
+        SYSCFG::enable(rcc);
+        Self { rb: syscfg }
+    }
+
+    /// Returns the memory currently aliased at address 0
+    pub fn memory_mode(&self) -> MemoryMode {
+        match self.rb.cfgr1().read().mem_mode().bits() {
+            0b01 => MemoryMode::SystemFlash,
+            0b11 => MemoryMode::Sram,
+            _ => MemoryMode::MainFlash,
+        }
+    }
+
+    /// Remaps the memory aliased at address 0, e.g. the system flash to jump to the bootloader
+    pub fn set_memory_mode(&mut self, mode: MemoryMode) {
+        self.rb
+            .cfgr1()
+            .modify(|_, w| unsafe { w.mem_mode().bits(mode as u8) });
+    }
+
+    /// Enables the 20 mA Fast-mode Plus drive on an I2C pin, or on all I2C1 pins
+    pub fn enable_fast_mode_plus(&mut self, fmp: FastModePlus) {
+        self.rb
+            .cfgr1()
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << fmp as u32)) });
+    }
+
+    pub fn disable_fast_mode_plus(&mut self, fmp: FastModePlus) {
+        self.rb
+            .cfgr1()
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << fmp as u32)) });
+    }
+
+    pub fn is_fast_mode_plus_enabled(&self, fmp: FastModePlus) -> bool {
+        self.rb.cfgr1().read().bits() & (1 << fmp as u32) != 0
+    }
+
+    pub fn release(self) -> SYSCFG {
+        self.rb
+    }
+}
+
+pub trait SysCfgExt {
+    fn constrain(self, rcc: &mut Rcc) -> SysCfg;
+}
+
+impl SysCfgExt for SYSCFG {
+    fn constrain(self, rcc: &mut Rcc) -> SysCfg {
+        SysCfg::new(self, rcc)
+    }
+}
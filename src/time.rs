@@ -47,6 +47,16 @@ impl Time {
             daylight_savings,
         }
     }
+
+    /// Returns the hours in 12-hour format (1 to 12) and whether it is PM
+    ///
+    /// `hours` itself always counts 0 to 23, whatever hour format the RTC uses.
+    pub fn hours_12h(&self) -> (u32, bool) {
+        match self.hours % 12 {
+            0 => (12, self.hours >= 12),
+            h => (h, self.hours >= 12),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -14,6 +14,26 @@ pub struct Delay<TIM> {
     tim: TIM,
 }
 
+/// Converts nanoseconds to clock cycles, rounding up so short delays never fall short
+///
+/// A non-zero request takes at least one cycle, the setup overhead of the delay loop
+/// only adds on top of that.
+fn ns_to_cycles(ns: u32, clk: Hertz) -> u64 {
+    if ns == 0 {
+        return 0;
+    }
+    let cycles = (ns as u64 * clk.raw() as u64 + 999_999_999) / 1_000_000_000;
+    cmp::max(cycles, 1)
+}
+
+/// Converts microseconds to clock cycles, rounding up
+///
+/// The count is kept in 64 bits, `delay_cycles` splits it into timer reloads, so
+/// long delays neither wrap nor saturate.
+fn us_to_cycles(us: u64, clk: Hertz) -> u64 {
+    (us * clk.raw() as u64 + 999_999) / 1_000_000
+}
+
 pub trait DelayExt<TIM> {
     fn delay(self, rcc: &mut Rcc) -> Delay<TIM>;
 }
@@ -29,11 +49,14 @@ impl Delay<SYST> {
     }
 
     pub fn delay(&mut self, delay: MicroSecond) {
-        let mut cycles = crate::time::cycles(delay, self.clk);
+        self.delay_cycles(us_to_cycles(delay.ticks() as u64, self.clk));
+    }
+
+    fn delay_cycles(&mut self, mut cycles: u64) {
         while cycles > 0 {
             let reload = cmp::min(cycles, 0x00ff_ffff);
             cycles -= reload;
-            self.tim.set_reload(reload);
+            self.tim.set_reload(reload as u32);
             self.tim.clear_current();
             self.tim.enable_counter();
             while !self.tim.has_wrapped() {}
@@ -67,7 +90,7 @@ impl DelayUs<u8> for Delay<SYST> {
 
 impl DelayMs<u32> for Delay<SYST> {
     fn delay_ms(&mut self, ms: u32) {
-        self.delay_us(ms.saturating_mul(1_000));
+        self.delay_cycles(us_to_cycles(ms as u64 * 1_000, self.clk));
     }
 }
 
@@ -83,6 +106,20 @@ impl DelayMs<u8> for Delay<SYST> {
     }
 }
 
+impl hal1::delay::DelayNs for Delay<SYST> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.delay_cycles(ns_to_cycles(ns, self.clk));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        self.delay_cycles(us_to_cycles(us as u64, self.clk));
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        self.delay_cycles(us_to_cycles(ms as u64 * 1_000, self.clk));
+    }
+}
+
 impl DelayExt<SYST> for SYST {
     fn delay(self, rcc: &mut Rcc) -> Delay<SYST> {
         Delay::syst(self, rcc)
@@ -105,7 +142,10 @@ macro_rules! delays {
                 }
 
                 pub fn delay(&mut self, delay: MicroSecond) {
-                    let mut cycles = crate::time::cycles(delay, self.clk);
+                    self.delay_cycles(us_to_cycles(delay.ticks() as u64, self.clk));
+                }
+
+                fn delay_cycles(&mut self, mut cycles: u64) {
                     while cycles > 0 {
                         let reload = cmp::min(cycles, 0xffff);
                         cycles -= reload;
@@ -143,7 +183,7 @@ macro_rules! delays {
 
             impl DelayMs<u32> for Delay<$TIM> {
                 fn delay_ms(&mut self, ms: u32) {
-                    self.delay_us(ms.saturating_mul(1_000));
+                    self.delay_cycles(us_to_cycles(ms as u64 * 1_000, self.clk));
                 }
             }
 
@@ -159,6 +199,20 @@ macro_rules! delays {
                 }
             }
 
+            impl hal1::delay::DelayNs for Delay<$TIM> {
+                fn delay_ns(&mut self, ns: u32) {
+                    self.delay_cycles(ns_to_cycles(ns, self.clk));
+                }
+
+                fn delay_us(&mut self, us: u32) {
+                    self.delay_cycles(us_to_cycles(us as u64, self.clk));
+                }
+
+                fn delay_ms(&mut self, ms: u32) {
+                    self.delay_cycles(us_to_cycles(ms as u64 * 1_000, self.clk));
+                }
+            }
+
             impl DelayExt<$TIM> for $TIM {
                 fn delay(self, rcc: &mut Rcc) -> Delay<$TIM> {
                     Delay::$tim(self, rcc)
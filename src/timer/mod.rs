@@ -1,4 +1,7 @@
 //! Timers
+//!
+//! The C0 parts have no LPTIM. Periodic wakeups from Stop mode can use the RTC alarm,
+//! with the sub-second mask for intervals below one second.
 use crate::rcc::*;
 use crate::stm32::*;
 use crate::time::{Hertz, MicroSecond};
@@ -9,9 +12,12 @@ use hal::timer::{CountDown, Periodic};
 use void::Void;
 
 pub mod delay;
+#[cfg(feature = "rtic")]
+pub mod monotonic;
 pub mod opm;
 pub mod pins;
 pub mod pwm;
+pub mod pwm_input;
 pub mod qei;
 pub mod stopwatch;
 
@@ -21,11 +27,75 @@ pub struct Timer<TIM> {
     tim: TIM,
 }
 
+/// Timer interrupt sources, DIER enable and SR flag bits share the same positions
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TimerInterrupt {
+    Update,
+    /// Capture/compare channel 1 to 4
+    Capture(u8),
+    Trigger,
+    Break,
+}
+
+impl TimerInterrupt {
+    pub(crate) fn mask(self) -> u32 {
+        match self {
+            TimerInterrupt::Update => 1,
+            TimerInterrupt::Capture(ch) => {
+                assert!((1..=4).contains(&ch));
+                1 << ch
+            }
+            TimerInterrupt::Trigger => 1 << 6,
+            TimerInterrupt::Break => 1 << 7,
+        }
+    }
+}
+
+/// Timeout of `Timer::start`, either as a period or as an update frequency
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TimerPeriod {
+    Period(MicroSecond),
+    Frequency(Hertz),
+}
+
+impl TimerPeriod {
+    /// Number of timer clock cycles per period, a frequency is converted directly
+    /// without rounding to whole microseconds first
+    pub(crate) fn cycles(self, clk: Hertz) -> u32 {
+        match self {
+            TimerPeriod::Period(period) => crate::time::cycles(period, clk),
+            TimerPeriod::Frequency(freq) => {
+                assert!(freq.raw() > 0);
+                clk.raw() / freq.raw()
+            }
+        }
+    }
+}
+
+impl From<MicroSecond> for TimerPeriod {
+    fn from(period: MicroSecond) -> Self {
+        TimerPeriod::Period(period)
+    }
+}
+
+impl From<Hertz> for TimerPeriod {
+    fn from(freq: Hertz) -> Self {
+        TimerPeriod::Frequency(freq)
+    }
+}
+
 pub struct Channel1;
 pub struct Channel2;
 pub struct Channel3;
 pub struct Channel4;
 
+impl<TIM> Timer<TIM> {
+    /// Returns the timer registers, for features the HAL doesn't wrap
+    pub fn raw(&mut self) -> &TIM {
+        &self.tim
+    }
+}
+
 /// System timer
 impl Timer<SYST> {
     /// Configures the SYST clock as a periodic count down timer
@@ -53,8 +123,11 @@ impl Timer<SYST> {
 }
 
 impl Timer<SYST> {
-    pub fn start(&mut self, timeout: MicroSecond) {
-        let cycles = crate::time::cycles(timeout, self.clk);
+    /// Starts the countdown, `timeout` is a period (`500.millis()`) or a frequency (`3.Hz()`)
+    ///
+    /// The 24-bit reload limits the period to 2^24 cycles of the SysTick clock.
+    pub fn start(&mut self, timeout: impl Into<TimerPeriod>) {
+        let cycles = timeout.into().cycles(self.clk);
         assert!(cycles < 0x00ff_ffff);
         self.tim.set_reload(cycles);
         self.tim.clear_current();
@@ -77,7 +150,8 @@ impl CountDown for Timer<SYST> {
     where
         T: Into<MicroSecond>,
     {
-        self.start(timeout.into())
+        let timeout: MicroSecond = timeout.into();
+        self.start(timeout)
     }
 
     fn wait(&mut self) -> nb::Result<(), Void> {
@@ -126,19 +200,44 @@ macro_rules! timers {
                     self.tim.cr1().modify(|_, w| w.cen().set_bit());
                 }
 
-                /// Starts listening
+                /// Starts listening for the update interrupt
+                ///
+                /// A stale update flag, e.g. from the UG event of `start`, is cleared first, so
+                /// the interrupt doesn't fire right away with a spurious tick.
                 pub fn listen(&mut self) {
-                    self.tim.dier().write(|w| w.uie().set_bit());
+                    self.clear_interrupt(TimerInterrupt::Update);
+                    self.listen_interrupt(TimerInterrupt::Update);
                 }
 
                 /// Stops listening
                 pub fn unlisten(&mut self) {
-                    self.tim.dier().write(|w| w.uie().clear_bit());
+                    self.unlisten_interrupt(TimerInterrupt::Update);
                 }
 
                 /// Clears interrupt flag
                 pub fn clear_irq(&mut self) {
-                    self.tim.sr().modify(|_, w| w.uif().clear_bit());
+                    self.clear_interrupt(TimerInterrupt::Update);
+                }
+
+                /// Enables an interrupt source, the timer may not implement all of them
+                pub fn listen_interrupt(&mut self, interrupt: TimerInterrupt) {
+                    self.tim.dier().modify(|r, w| unsafe { w.bits(r.bits() | interrupt.mask()) });
+                }
+
+                /// Disables an interrupt source
+                pub fn unlisten_interrupt(&mut self, interrupt: TimerInterrupt) {
+                    self.tim.dier().modify(|r, w| unsafe { w.bits(r.bits() & !interrupt.mask()) });
+                }
+
+                /// Checks the flag of an interrupt source
+                pub fn is_interrupt_pending(&self, interrupt: TimerInterrupt) -> bool {
+                    self.tim.sr().read().bits() & interrupt.mask() != 0
+                }
+
+                /// Clears the flag of an interrupt source, leaving the others untouched
+                pub fn clear_interrupt(&mut self, interrupt: TimerInterrupt) {
+                    // NOTE(unsafe) flags are cleared by writing 0, writing 1 has no effect
+                    self.tim.sr().write(|w| unsafe { w.bits(!interrupt.mask()) });
                 }
 
                 /// Resets counter value
@@ -156,7 +255,14 @@ macro_rules! timers {
                     low | (_high << 16)
                 }
 
-                pub fn start(&mut self, timeout: MicroSecond) {
+                /// Starts the countdown, `timeout` is a period (`500.millis()`) or a frequency
+                /// (`3.Hz()`)
+                ///
+                /// The prescaler is the smallest one that fits the period in the 16-bit ARR,
+                /// so the resolution is one timer clock cycle up to 65535 cycles and
+                /// `PSC + 1` cycles beyond, with a period of at most 2^32 cycles of
+                /// `apb_tim_clk`.
+                pub fn start(&mut self, timeout: impl Into<TimerPeriod>) {
                     // Pause the counter. Also set URS so that when we set UG below, it will
                     // generate an update event *without* triggering an interrupt.
                     self.tim.cr1().modify(|_, w| w.cen().clear_bit().urs().set_bit());
@@ -166,7 +272,7 @@ macro_rules! timers {
                     self.tim.sr().modify(|_, w| w.uif().clear_bit());
 
                     // Calculate counter configuration
-                    let cycles = crate::time::cycles(timeout, self.clk);
+                    let cycles = timeout.into().cycles(self.clk);
                     let psc = cycles / 0xffff;
                     let arr = cycles / (psc + 1);
 
@@ -208,7 +314,8 @@ macro_rules! timers {
                 where
                     T: Into<MicroSecond>,
                 {
-                    self.start(timeout.into())
+                    let timeout: MicroSecond = timeout.into();
+                    self.start(timeout)
                 }
 
                 fn wait(&mut self) -> nb::Result<(), Void> {
@@ -234,6 +341,9 @@ macro_rules! timers_external_clocks {
     ($($TIM:ident: ($tim:ident, $sms:ident $(,$ece:ident)*),)+) => {
         $(
             impl Timer<$TIM> {
+                /// Clocks the counter from a trigger pin (mode 1) or the ETR pin (mode 2).
+                /// `freq` is the nominal external clock used for `start` timeouts, when
+                /// counting pulses read them back with `get_current`.
                 pub fn use_external_clock<C: ExternalClock>(&mut self, clk: C, freq: Hertz) {
                     self.clk = freq;
                     match clk.mode() {
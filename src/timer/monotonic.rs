@@ -0,0 +1,115 @@
+//! RTIC Monotonic implementation
+//!
+//! The counter is 16 bits wide, the upper bits of the instant are extended in
+//! software by counting update events. The update interrupt fires on each
+//! counter wrap, so the timer interrupt stays enabled even with an empty queue.
+//!
+//! TIM1 is not supported: its update and compare events are routed to separate
+//! vectors (`TIM1_BRK_UP_TRG_COM` and `TIM1_CC`) while `#[monotonic]` binds one.
+use crate::rcc::*;
+use crate::stm32::*;
+use fugit::{TimerDurationU64, TimerInstantU64};
+use rtic_monotonic::Monotonic;
+
+pub struct MonoTimer<TIM, const FREQ: u32> {
+    tim: TIM,
+    ovf: u64,
+}
+
+pub trait MonoTimerExt: Sized {
+    fn monotonic<const FREQ: u32>(self, rcc: &mut Rcc) -> MonoTimer<Self, FREQ>;
+}
+
+macro_rules! monotonic {
+    ($($TIM:ident: $tim:ident,)+) => {
+        $(
+            impl<const FREQ: u32> MonoTimer<$TIM, FREQ> {
+                /// Configures a TIM peripheral as a monotonic counter ticking at `FREQ`
+                pub fn $tim(tim: $TIM, rcc: &mut Rcc) -> Self {
+                    $TIM::enable(rcc);
+                    $TIM::reset(rcc);
+
+                    let clk = rcc.clocks.apb_tim_clk.raw();
+                    assert!(FREQ <= clk, "monotonic FREQ exceeds the timer clock");
+                    assert!(clk % FREQ == 0, "timer clock is not a multiple of monotonic FREQ");
+                    let psc = clk / FREQ - 1;
+                    assert!(psc <= 0xffff, "monotonic FREQ is too low for the 16-bit prescaler");
+                    tim.psc().write(|w| unsafe { w.psc().bits(psc as u16) });
+                    tim.arr().write(|w| unsafe { w.bits(0xffff) });
+
+                    MonoTimer { tim, ovf: 0 }
+                }
+
+                /// Releases the TIM peripheral
+                pub fn release(self) -> $TIM {
+                    self.tim
+                }
+            }
+
+            impl MonoTimerExt for $TIM {
+                fn monotonic<const FREQ: u32>(self, rcc: &mut Rcc) -> MonoTimer<Self, FREQ> {
+                    MonoTimer::$tim(self, rcc)
+                }
+            }
+
+            impl<const FREQ: u32> Monotonic for MonoTimer<$TIM, FREQ> {
+                const DISABLE_INTERRUPT_ON_EMPTY_QUEUE: bool = false;
+
+                type Instant = TimerInstantU64<FREQ>;
+                type Duration = TimerDurationU64<FREQ>;
+
+                fn now(&mut self) -> Self::Instant {
+                    let cnt = self.tim.cnt().read().bits() as u64 & 0xffff;
+
+                    // An update event that is not yet handled by on_interrupt means the
+                    // counter just wrapped, if the counter is still in the lower half
+                    let ovf = if self.tim.sr().read().uif().bit_is_set() && cnt < 0x8000 {
+                        self.ovf + 0x1_0000
+                    } else {
+                        self.ovf
+                    };
+
+                    Self::Instant::from_ticks(ovf | cnt)
+                }
+
+                fn set_compare(&mut self, instant: Self::Instant) {
+                    // Targets beyond the current period trigger a spurious compare event,
+                    // the timer queue will re-arm the compare after the overflow
+                    let ccr = instant.ticks() & 0xffff;
+                    self.tim.ccr1().write(|w| unsafe { w.bits(ccr as _) });
+                }
+
+                fn clear_compare_flag(&mut self) {
+                    // NOTE(unsafe) flags are cleared by writing 0, writing 1 has no effect
+                    self.tim.sr().write(|w| unsafe { w.bits(!(1 << 1)) });
+                }
+
+                fn on_interrupt(&mut self) {
+                    if self.tim.sr().read().uif().bit_is_set() {
+                        // NOTE(unsafe) flags are cleared by writing 0, writing 1 has no effect
+                        self.tim.sr().write(|w| unsafe { w.bits(!1) });
+                        self.ovf += 0x1_0000;
+                    }
+                }
+
+                fn zero() -> Self::Instant {
+                    Self::Instant::from_ticks(0)
+                }
+
+                unsafe fn reset(&mut self) {
+                    self.ovf = 0;
+                    // Load the prescaler into the shadow register, without an interrupt
+                    self.tim.cr1().modify(|_, w| w.urs().set_bit());
+                    self.tim.egr().write(|w| w.ug().set_bit());
+                    self.tim.sr().write(|w| w.bits(0));
+                    self.tim.dier().write(|w| w.uie().set_bit().cc1ie().set_bit());
+                    self.tim.cr1().modify(|_, w| w.cen().set_bit());
+                }
+            }
+        )+
+    }
+}
+
+monotonic! {
+    TIM3: tim3,
+}
@@ -11,6 +11,12 @@ pub trait OpmExt: Sized {
     fn opm(self, period: MicroSecond, rcc: &mut Rcc) -> Opm<Self>;
 }
 
+/// Output pulse polarity
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
 pub struct OpmPin<TIM, CH> {
     tim: PhantomData<TIM>,
     channel: PhantomData<CH>,
@@ -23,9 +29,9 @@ pub struct Opm<TIM> {
 }
 
 impl<TIM> Opm<TIM> {
-    pub fn bind_pin<PIN>(&self, pin: PIN) -> OpmPin<TIM, PIN::Channel>
+    pub fn bind_pin<PIN, CH>(&self, pin: PIN) -> OpmPin<TIM, CH>
     where
-        PIN: TimerPin<TIM>,
+        PIN: TimerPin<TIM, CH>,
     {
         pin.setup();
         OpmPin {
@@ -78,6 +84,30 @@ macro_rules! opm {
                     let tim =  unsafe {&*$TIMX::ptr()};
                     tim.cr1().write(|w| w.opm().set_bit().cen().set_bit());
                 }
+
+                /// Returns true while a pulse is in progress, the counter is stopped by
+                /// hardware at the end of the pulse
+                pub fn is_active(&self) -> bool {
+                    let tim =  unsafe {&*$TIMX::ptr()};
+                    tim.cr1().read().cen().bit_is_set()
+                }
+
+                /// Raise the update interrupt when a pulse has completed
+                pub fn listen_complete(&mut self) {
+                    let tim =  unsafe {&*$TIMX::ptr()};
+                    tim.dier().modify(|_, w| w.uie().set_bit());
+                }
+
+                pub fn unlisten_complete(&mut self) {
+                    let tim =  unsafe {&*$TIMX::ptr()};
+                    tim.dier().modify(|_, w| w.uie().clear_bit());
+                }
+
+                /// Clears the pulse complete (update) flag
+                pub fn clear_complete(&mut self) {
+                    let tim =  unsafe {&*$TIMX::ptr()};
+                    tim.sr().write(|w| unsafe { w.bits(!TimerInterrupt::Update.mask()) });
+                }
             }
         )+
     }
@@ -85,7 +115,7 @@ macro_rules! opm {
 
 macro_rules! opm_hal {
     ($($TIMX:ident:
-        ($CH:ty, $ccxe:ident, $ccmrx_output:ident, $ocxm:ident, $ocxfe:ident, $ccrx:ident),)+
+        ($CH:ty, $ccxe:ident, $ccxp:ident, $ccmrx_output:ident, $ocxm:ident, $ocxfe:ident, $ccrx:ident),)+
     ) => {
         $(
             impl OpmPin<$TIMX, $CH> {
@@ -100,6 +130,11 @@ macro_rules! opm_hal {
                     tim.ccer().modify(|_, w| w.$ccxe().clear_bit());
                 }
 
+                pub fn set_polarity(&mut self, polarity: Polarity) {
+                    let tim =  unsafe {&*$TIMX::ptr()};
+                    tim.ccer().modify(|_, w| w.$ccxp().bit(matches!(polarity, Polarity::ActiveLow)));
+                }
+
                 pub fn get_max_delay(&mut self) -> u32 {
                     unsafe { (*$TIMX::ptr()).arr().read().bits() as _ }
                 }
@@ -122,17 +157,40 @@ macro_rules! opm_hal {
 }
 
 opm_hal! {
-    TIM1: (Channel1, cc1e, ccmr1_output, oc1m1, oc1fe, ccr1),
-    TIM1: (Channel2, cc2e, ccmr1_output, oc2m1, oc2fe, ccr2),
-    TIM1: (Channel3, cc3e, ccmr2_output, oc3m1, oc3fe, ccr3),
-    TIM1: (Channel4, cc4e, ccmr2_output, oc4m1, oc4fe, ccr4),
-    TIM3: (Channel1, cc1e, ccmr1_output, oc1m1, oc1fe, ccr1),
-    TIM3: (Channel2, cc2e, ccmr1_output, oc2m1, oc2fe, ccr2),
-    TIM3: (Channel3, cc3e, ccmr2_output, oc3m1, oc3fe, ccr3),
-    TIM3: (Channel4, cc4e, ccmr2_output, oc4m1, oc4fe, ccr4),
-    TIM14: (Channel1, cc1e, ccmr1_output, oc1m1, oc1fe, ccr1),
-    TIM16: (Channel1, cc1e, ccmr1_output, oc1m1, oc1fe, ccr1),
-    TIM17: (Channel1, cc1e, ccmr1_output, oc1m1, oc1fe, ccr1),
+    TIM1: (Channel1, cc1e, cc1p, ccmr1_output, oc1m1, oc1fe, ccr1),
+    TIM1: (Channel2, cc2e, cc2p, ccmr1_output, oc2m1, oc2fe, ccr2),
+    TIM1: (Channel3, cc3e, cc3p, ccmr2_output, oc3m1, oc3fe, ccr3),
+    TIM1: (Channel4, cc4e, cc4p, ccmr2_output, oc4m1, oc4fe, ccr4),
+    TIM3: (Channel1, cc1e, cc1p, ccmr1_output, oc1m1, oc1fe, ccr1),
+    TIM3: (Channel2, cc2e, cc2p, ccmr1_output, oc2m1, oc2fe, ccr2),
+    TIM3: (Channel3, cc3e, cc3p, ccmr2_output, oc3m1, oc3fe, ccr3),
+    TIM3: (Channel4, cc4e, cc4p, ccmr2_output, oc4m1, oc4fe, ccr4),
+    TIM14: (Channel1, cc1e, cc1p, ccmr1_output, oc1m1, oc1fe, ccr1),
+    TIM16: (Channel1, cc1e, cc1p, ccmr1_output, oc1m1, oc1fe, ccr1),
+    TIM17: (Channel1, cc1e, cc1p, ccmr1_output, oc1m1, oc1fe, ccr1),
+}
+
+macro_rules! opm_repetition {
+    ($($TIMX:ident,)+) => {
+        $(
+            impl Opm<$TIMX> {
+                /// Repeat the pulse `n` more times on each `generate()`
+                pub fn set_repetitions(&mut self, n: u8) {
+                    let tim =  unsafe {&*$TIMX::ptr()};
+                    tim.rcr().write(|w| unsafe { w.rep().bits(n as _) });
+                    // Load the repetition counter without raising an update interrupt
+                    tim.cr1().modify(|_, w| w.urs().set_bit());
+                    tim.egr().write(|w| w.ug().set_bit());
+                }
+            }
+        )+
+    }
+}
+
+opm_repetition! {
+    TIM1,
+    TIM16,
+    TIM17,
 }
 
 opm! {
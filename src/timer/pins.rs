@@ -3,25 +3,53 @@ use crate::gpio::{AltFunction, DefaultMode};
 use crate::stm32::*;
 use crate::timer::*;
 
-pub trait TimerPin<TIM> {
-    type Channel;
-
+/// Timer channel pin
+///
+/// The implementation is keyed on the channel, so a single pad can map to
+/// several channels of the same timer using different alternate functions.
+pub trait TimerPin<TIM, CH> {
     fn setup(&self);
     fn release(self) -> Self;
 }
 
-pub struct TriggerPin<TIM, PIN: TimerPin<TIM>> {
+pub struct TriggerPin<TIM, PIN> {
     pin: PIN,
     tim: PhantomData<TIM>,
 }
 
-impl<TIM, PIN: TimerPin<TIM>> ExternalClock for TriggerPin<TIM, PIN> {
+impl<TIM, PIN> ExternalClock for TriggerPin<TIM, PIN> {
     fn mode(&self) -> ExternalClockMode {
         ExternalClockMode::Mode1
     }
 }
 
-impl<TIM, PIN: TimerPin<TIM>> TriggerPin<TIM, PIN> {
+impl<TIM, PIN> TriggerPin<TIM, PIN> {
+    pub fn release(self) -> PIN {
+        self.pin
+    }
+}
+
+/// External trigger (ETR) input, clocks the timer in external clock mode 2
+pub struct EtrPin<TIM, PIN> {
+    pin: PIN,
+    tim: PhantomData<TIM>,
+}
+
+/// ETR prescaler, the external clock must stay below a quarter of the timer clock
+pub enum EtrPrescaler {
+    Div1 = 0,
+    Div2 = 1,
+    Div4 = 2,
+    Div8 = 3,
+}
+
+impl<TIM, PIN> ExternalClock for EtrPin<TIM, PIN> {
+    fn mode(&self) -> ExternalClockMode {
+        ExternalClockMode::Mode2
+    }
+}
+
+impl<TIM, PIN> EtrPin<TIM, PIN> {
     pub fn release(self) -> PIN {
         self.pin
     }
@@ -30,9 +58,7 @@ impl<TIM, PIN: TimerPin<TIM>> TriggerPin<TIM, PIN> {
 macro_rules! timer_pins {
     ($TIMX:ident, [ $(($ch:ty, $pin:ty, $af_mode:expr),)+ ]) => {
         $(
-            impl TimerPin<$TIMX> for $pin {
-                type Channel = $ch;
-
+            impl TimerPin<$TIMX, $ch> for $pin {
                 fn setup(&self) {
                     self.set_alt_mode($af_mode);
                 }
@@ -46,11 +72,11 @@ macro_rules! timer_pins {
 }
 
 macro_rules! trigger_pins {
-    ($TIMX:ident, [ $(($pin:ty, $ccp:ident $(,$icf:ident)*),)+ ]) => {
+    ($TIMX:ident, [ $(($ch:ty, $pin:ty, $ccp:ident $(,$icf:ident)*),)+ ]) => {
         $(
             impl TriggerPin<$TIMX, $pin> {
                 pub fn new(pin: $pin, edge: SignalEdge) -> Self {
-                    TimerPin::<$TIMX>::setup(&pin);
+                    TimerPin::<$TIMX, $ch>::setup(&pin);
                     let tim = unsafe { &(*$TIMX::ptr()) };
                     let ts = match edge {
                         SignalEdge::All => 0b100,
@@ -86,22 +112,57 @@ macro_rules! trigger_pins {
     };
 }
 
+macro_rules! etr_pins {
+    ($TIMX:ident, [ $(($pin:ty, $af_mode:expr),)+ ]) => {
+        $(
+            impl EtrPin<$TIMX, $pin> {
+                /// Counts rising or falling edges on ETR, `filter` is the 4 bit ETF setting
+                pub fn new(pin: $pin, edge: SingleEdge, prescaler: EtrPrescaler, filter: u8) -> Self {
+                    pin.set_alt_mode($af_mode);
+                    let tim = unsafe { &(*$TIMX::ptr()) };
+                    tim.smcr().modify(|_, w| unsafe {
+                        w.etp()
+                            .bit(edge == SingleEdge::Falling)
+                            .etps()
+                            .bits(prescaler as u8)
+                            .etf()
+                            .bits(filter & 0xf)
+                    });
+
+                    Self {
+                        pin,
+                        tim: PhantomData,
+                    }
+                }
+            }
+        )+
+    };
+}
+
+etr_pins!(TIM1, [
+    (PA12<DefaultMode>, AltFunction::AF2),
+]);
+
+etr_pins!(TIM3, [
+    (PD2<DefaultMode>, AltFunction::AF1),
+]);
+
 // TODO: port pin mapping
 trigger_pins!(TIM1, [
-    (PA8<DefaultMode>, cc1p),
-    (PC8<DefaultMode>, cc1p),
-    (PA9<DefaultMode>, cc2p),
-    (PB3<DefaultMode>, cc2p),
-    (PC9<DefaultMode>, cc2p),
+    (Channel1, PA8<DefaultMode>, cc1p),
+    (Channel1, PC8<DefaultMode>, cc1p),
+    (Channel2, PA9<DefaultMode>, cc2p),
+    (Channel2, PB3<DefaultMode>, cc2p),
+    (Channel2, PC9<DefaultMode>, cc2p),
 ]);
 
 trigger_pins!(TIM3, [
-    (PA6<DefaultMode>, cc1p, ic1f),
-    (PB4<DefaultMode>, cc1p, ic1f),
-    (PC6<DefaultMode>, cc1p, ic1f),
-    (PA7<DefaultMode>, cc2p, ic2f),
-    (PB5<DefaultMode>, cc2p, ic2f),
-    (PC7<DefaultMode>, cc2p, ic2f),
+    (Channel1, PA6<DefaultMode>, cc1p, ic1f),
+    (Channel1, PB4<DefaultMode>, cc1p, ic1f),
+    (Channel1, PC6<DefaultMode>, cc1p, ic1f),
+    (Channel2, PA7<DefaultMode>, cc2p, ic2f),
+    (Channel2, PB5<DefaultMode>, cc2p, ic2f),
+    (Channel2, PC7<DefaultMode>, cc2p, ic2f),
 ]);
 
 timer_pins!(TIM1, [
@@ -117,6 +178,11 @@ timer_pins!(TIM1, [
     (Channel4, PC11<DefaultMode>, AltFunction::AF2),
 ]);
 
+#[cfg(feature = "stm32c011")]
+timer_pins!(TIM1, [
+    (Channel2, PB6<DefaultMode>, AltFunction::AF11),
+]);
+
 // Inverted pins
 timer_pins!(TIM1, [
     (Channel1, PA7<DefaultMode>, AltFunction::AF2),
@@ -1,9 +1,11 @@
 //! # Pulse Width Modulation
+use core::cmp;
+use core::convert::{TryFrom, TryInto};
 use core::marker::PhantomData;
 
 use crate::rcc::*;
 use crate::stm32::*;
-use crate::time::Hertz;
+use crate::time::{Hertz, MicroSecond};
 use crate::timer::pins::TimerPin;
 use crate::timer::*;
 
@@ -24,6 +26,61 @@ pub enum OutputCompareMode {
     AsyncMode2 = 15,
 }
 
+/// Master mode selection, the event routed to TRGO
+pub enum TrgoSource {
+    Reset = 0,
+    Enable = 1,
+    Update = 2,
+    ComparePulse = 3,
+    OC1Ref = 4,
+    OC2Ref = 5,
+    OC3Ref = 6,
+    OC4Ref = 7,
+}
+
+/// Slave mode trigger selection
+///
+/// Internal trigger connections on the C0 (RM0490):
+/// - TIM1: ITR2 is TIM3 TRGO, ITR3 is TIM17 OC1
+/// - TIM3: ITR0 is TIM1 TRGO, ITR3 is TIM14 OC1
+pub enum TriggerSource {
+    ITR0 = 0,
+    ITR1 = 1,
+    ITR2 = 2,
+    ITR3 = 3,
+    TI1FEdge = 4,
+    TI1FP1 = 5,
+    TI2FP2 = 6,
+    ETRF = 7,
+}
+
+/// Slave mode selection
+pub enum SlaveMode {
+    Disabled = 0,
+    /// Reinitialize the counter on the trigger rising edge
+    Reset = 4,
+    /// Counter runs while the trigger is high
+    Gated = 5,
+    /// Counter is started on the trigger rising edge
+    Trigger = 6,
+    /// Trigger rising edges clock the counter
+    ExternalClock = 7,
+}
+
+/// Counting mode
+pub enum CountDirection {
+    /// Count up from 0 to ARR
+    Up,
+    /// Count down from ARR to 0
+    Down,
+    /// Count up and down, compare flags are set while counting down
+    CenterAligned1,
+    /// Count up and down, compare flags are set while counting up
+    CenterAligned2,
+    /// Count up and down, compare flags are set in both directions
+    CenterAligned3,
+}
+
 pub struct Pwm<TIM> {
     clk: Hertz,
     tim: TIM,
@@ -39,13 +96,20 @@ pub trait PwmExt: Sized {
 }
 
 pub trait PwmPinMode {
+    /// Select the output compare mode. `enable` keeps this mode, it only selects
+    /// PWM mode 1 while the channel is still frozen.
     fn set_compare_mode(&mut self, mode: OutputCompareMode);
 }
 
 impl<TIM> Pwm<TIM> {
-    pub fn bind_pin<PIN>(&self, pin: PIN) -> PwmPin<TIM, PIN::Channel>
+    /// Returns the timer registers, for features the HAL doesn't wrap
+    pub fn raw(&mut self) -> &TIM {
+        &self.tim
+    }
+
+    pub fn bind_pin<PIN, CH>(&self, pin: PIN) -> PwmPin<TIM, CH>
     where
-        PIN: TimerPin<TIM>,
+        PIN: TimerPin<TIM, CH>,
     {
         pin.setup();
         PwmPin {
@@ -55,6 +119,42 @@ impl<TIM> Pwm<TIM> {
     }
 }
 
+impl<TIM, CH> PwmPin<TIM, CH>
+where
+    Self: hal::PwmPin,
+    <Self as hal::PwmPin>::Duty: Into<u32> + TryFrom<u32>,
+{
+    /// Set the duty cycle as a `num / den` fraction of the max duty.
+    /// Fractions above 1 are clamped to the max duty.
+    pub fn set_duty_fraction(&mut self, num: u16, den: u16) {
+        let max = Into::<u32>::into(hal::PwmPin::get_max_duty(self)) as u64;
+        let duty = if den == 0 {
+            0
+        } else {
+            max * cmp::min(num, den) as u64 / den as u64
+        };
+        // The duty is at most the max duty, so it always fits the duty type
+        if let Ok(duty) = (duty as u32).try_into() {
+            hal::PwmPin::set_duty(self, duty);
+        }
+    }
+
+    /// Set the duty cycle in percent, values above 100 are clamped
+    pub fn set_duty_percent(&mut self, pct: u8) {
+        self.set_duty_fraction(pct as u16, 100);
+    }
+
+    /// Returns the current duty cycle in percent
+    pub fn get_duty_percent(&self) -> u8 {
+        let max = Into::<u32>::into(hal::PwmPin::get_max_duty(self)) as u64;
+        if max == 0 {
+            return 0;
+        }
+        let duty = Into::<u32>::into(hal::PwmPin::get_duty(self)) as u64;
+        cmp::min(duty * 100 / max, 100) as u8
+    }
+}
+
 macro_rules! pwm {
     ($($TIMX:ident: ($timX:ident, $arr:ident $(,$arr_h:ident)*),)+) => {
         $(
@@ -82,9 +182,63 @@ macro_rules! pwm {
                 /// requested due to precision of input clock. To check actual
                 /// frequency, call freq.
                 pub fn set_freq(&mut self, freq: Hertz) {
-                    let ratio = self.clk / freq;
-                    let psc = (ratio - 1) / 0xffff;
-                    let arr = ratio / (psc + 1) - 1;
+                    self.set_cycles(self.clk / freq / self.periods_per_cycle());
+                    self.tim.cr1().modify(|_, w| w.cen().set_bit());
+                }
+
+                /// Buffer ARR writes, so a new frequency takes effect at the next update
+                /// event instead of in the middle of a cycle. Enabling it generates an
+                /// update event to load the current ARR, PSC and compare values at once,
+                /// which restarts the counter.
+                pub fn set_auto_reload_preload(&mut self, enable: bool) {
+                    self.tim.cr1().modify(|_, w| w.arpe().bit(enable));
+                    if enable {
+                        self.tim.egr().write(|w| w.ug().set_bit());
+                    }
+                }
+
+                /// Set the counter period, which is also the update event rate.
+                /// Unlike `set_freq` this does not start the counter.
+                pub fn set_period(&mut self, period: MicroSecond) {
+                    self.set_cycles(crate::time::cycles(period, self.clk) / self.periods_per_cycle());
+                }
+
+                /// Returns the currently configured counter period
+                pub fn period(&self) -> MicroSecond {
+                    // Up to 2^33 cycles with both registers at 0xffff in center-aligned mode
+                    let cycles = (self.tim.psc().read().bits() as u64 + 1)
+                        * (self.tim.arr().read().bits() as u64 + 1)
+                        * self.periods_per_cycle() as u64;
+                    let us = cycles * 1_000_000 / self.clk.raw() as u64;
+                    MicroSecond::from_ticks(cmp::min(us, u32::MAX as u64) as u32)
+                }
+
+                /// Returns the current ARR, the duty value of a 100% duty cycle on
+                /// every channel
+                pub fn max_duty(&self) -> u32 {
+                    self.tim.arr().read().bits()
+                }
+
+                /// Returns the prescaler value
+                pub fn get_prescaler(&self) -> u16 {
+                    self.tim.psc().read().psc().bits()
+                }
+
+                /// Pauses the counter
+                pub fn pause(&mut self) {
+                    self.tim.cr1().modify(|_, w| w.cen().clear_bit());
+                }
+
+                /// Resumes the counter
+                pub fn resume(&mut self) {
+                    self.tim.cr1().modify(|_, w| w.cen().set_bit());
+                }
+
+                fn set_cycles(&mut self, cycles: u32) {
+                    // A period shorter than the timer clock still needs one cycle
+                    let cycles = cmp::max(cycles, 1);
+                    let psc = (cycles - 1) / 0xffff;
+                    let arr = cycles / (psc + 1) - 1;
 
                     unsafe {
                         self.tim.psc().write(|w| w.psc().bits(psc as u16));
@@ -92,21 +246,47 @@ macro_rules! pwm {
                         $(
                             self.tim.arr().modify(|_, w| w.$arr_h().bits((arr >> 16) as u16));
                         )*
-                        self.tim.cr1().write(|w| w.cen().set_bit())
                     }
                 }
-                /// Starts listening
+
+                /// Starts listening for the update interrupt
+                ///
+                /// A stale update flag, e.g. from an earlier UG event, is cleared first, so
+                /// the interrupt doesn't fire right away with a spurious tick.
                 pub fn listen(&mut self) {
-                    self.tim.dier().write(|w| w.uie().set_bit());
+                    self.clear_interrupt(TimerInterrupt::Update);
+                    self.listen_interrupt(TimerInterrupt::Update);
                 }
 
                 /// Stops listening
                 pub fn unlisten(&mut self) {
-                    self.tim.dier().write(|w| w.uie().clear_bit());
+                    self.unlisten_interrupt(TimerInterrupt::Update);
                 }
+
                 /// Clears interrupt flag
                 pub fn clear_irq(&mut self) {
-                    self.tim.sr().modify(|_, w| w.uif().clear_bit());
+                    self.clear_interrupt(TimerInterrupt::Update);
+                }
+
+                /// Enables an interrupt source, the timer may not implement all of them
+                pub fn listen_interrupt(&mut self, interrupt: TimerInterrupt) {
+                    self.tim.dier().modify(|r, w| unsafe { w.bits(r.bits() | interrupt.mask()) });
+                }
+
+                /// Disables an interrupt source
+                pub fn unlisten_interrupt(&mut self, interrupt: TimerInterrupt) {
+                    self.tim.dier().modify(|r, w| unsafe { w.bits(r.bits() & !interrupt.mask()) });
+                }
+
+                /// Checks the flag of an interrupt source
+                pub fn is_interrupt_pending(&self, interrupt: TimerInterrupt) -> bool {
+                    self.tim.sr().read().bits() & interrupt.mask() != 0
+                }
+
+                /// Clears the flag of an interrupt source, leaving the others untouched
+                pub fn clear_interrupt(&mut self, interrupt: TimerInterrupt) {
+                    // NOTE(unsafe) flags are cleared by writing 0, writing 1 has no effect
+                    self.tim.sr().write(|w| unsafe { w.bits(!interrupt.mask()) });
                 }
 
                 /// Resets counter value
@@ -114,11 +294,99 @@ macro_rules! pwm {
                     self.tim.cnt().reset();
                 }
 
+                /// Returns the counter value. All C0 timers are 16 bit, the UIF copy
+                /// in bit 31 is masked out.
+                pub fn counter(&self) -> u32 {
+                    self.tim.cnt().read().bits() & 0xffff
+                }
+
+                /// Presets the counter, e.g. to shift the phase against another timer
+                pub fn set_counter(&mut self, value: u32) {
+                    self.tim.cnt().write(|w| unsafe { w.bits(value & 0xffff) });
+                }
+
                 /// Returns the currently configured frequency
                 pub fn freq(&self) -> Hertz {
                     Hertz::from_raw(self.clk.raw()
                         / (self.tim.psc().read().bits() as u32 + 1)
-                        / (self.tim.arr().read().bits() as u32 + 1))
+                        / (self.tim.arr().read().bits() as u32 + 1)
+                        / self.periods_per_cycle())
+                }
+
+                /// A center-aligned counter needs two passes over ARR for one PWM cycle.
+                /// CMS is reserved and reads as zero on timers that only count up.
+                fn periods_per_cycle(&self) -> u32 {
+                    if (self.tim.cr1().read().bits() >> 5) & 0b11 != 0 {
+                        2
+                    } else {
+                        1
+                    }
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! pwm_sync {
+    ($($TIMX:ident,)+) => {
+        $(
+            impl Pwm<$TIMX> {
+                /// Select the event this timer outputs on TRGO as a master
+                pub fn set_master_mode(&mut self, source: TrgoSource) {
+                    self.tim.cr2().modify(|_, w| unsafe { w.mms().bits(source as u8) });
+                    self.tim.smcr().modify(|_, w| w.msm().set_bit());
+                }
+
+                /// Synchronize this timer as a slave to the given trigger input.
+                /// The counter is already running after construction, use `SlaveMode::Reset`
+                /// to align it with the master or `pause` it before using `SlaveMode::Trigger`.
+                pub fn set_slave_mode(&mut self, source: TriggerSource, mode: SlaveMode) {
+                    self.tim.smcr().modify(|_, w| unsafe {
+                        w.ts1().bits(source as u8).sms1().bits(mode as u8)
+                    });
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! pwm_direction {
+    ($($TIMX:ident,)+) => {
+        $(
+            impl Pwm<$TIMX> {
+                /// Select the counting mode. The counter is stopped while CMS is changed and
+                /// restarted afterwards if it was running. The frequency is not adjusted,
+                /// call `set_freq` again after switching between edge and center-aligned.
+                pub fn set_direction(&mut self, direction: CountDirection) {
+                    let (dir, cms) = match direction {
+                        CountDirection::Up => (false, 0b00),
+                        CountDirection::Down => (true, 0b00),
+                        CountDirection::CenterAligned1 => (false, 0b01),
+                        CountDirection::CenterAligned2 => (false, 0b10),
+                        CountDirection::CenterAligned3 => (false, 0b11),
+                    };
+                    let enabled = self.tim.cr1().read().cen().bit_is_set();
+                    self.tim.cr1().modify(|_, w| w.cen().clear_bit());
+                    self.tim.cr1().modify(|_, w| unsafe { w.cms().bits(cms).dir().bit(dir) });
+                    if enabled {
+                        self.tim.cr1().modify(|_, w| w.cen().set_bit());
+                    }
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! pwm_repetition {
+    ($($TIMX:ident,)+) => {
+        $(
+            impl Pwm<$TIMX> {
+                /// Raise the update event (and its interrupt) only every `n + 1` counter
+                /// overflows, in center-aligned mode every `n + 1` half periods. The PWM
+                /// frequency is unchanged. The new value is loaded when the current
+                /// repetition cycle ends, so the running output isn't disturbed.
+                pub fn set_repetition(&mut self, n: u8) {
+                    self.tim.rcr().write(|w| unsafe { w.rep().bits(n as _) });
                 }
             }
         )+
@@ -155,7 +423,14 @@ macro_rules! pwm_hal {
                 fn enable(&mut self) {
                     unsafe {
                         let tim = &*$TIMX::ptr();
-                        tim.$ccmrx_output().modify(|_, w| w.$ocxpe().set_bit().$ocxm().bits(6));
+                        // Keep a compare mode set with `set_compare_mode`, default to PWM mode 1
+                        tim.$ccmrx_output().modify(|r, w| {
+                            let mode = match r.$ocxm().bits() {
+                                0 => OutputCompareMode::PwmMode1 as u8,
+                                mode => mode,
+                            };
+                            w.$ocxpe().set_bit().$ocxm().bits(mode)
+                        });
                         tim.ccer().modify(|_, w| w.$ccxe().set_bit());
                     }
                 }
@@ -172,6 +447,15 @@ macro_rules! pwm_hal {
                     unsafe { (*$TIMX::ptr()).$ccrx().write(|w| w.bits(duty)) }
                 }
             }
+
+            impl PwmPinMode for PwmPin<$TIMX, $CH>{
+                fn set_compare_mode(&mut self, mode: OutputCompareMode) {
+                    unsafe {
+                        let tim = &*$TIMX::ptr();
+                        tim.$ccmrx_output().modify(|_, w| w.$ocxm().bits(mode as u8));
+                    }
+                }
+            }
         )+
     };
 }
@@ -200,7 +484,14 @@ macro_rules! pwm_advanced_hal {
                 fn enable(&mut self) {
                     unsafe {
                         let tim = &*$TIMX::ptr();
-                        tim.$ccmrx_output().modify(|_, w| w.$ocxpe().set_bit().$ocxm().bits(6));
+                        // Keep a compare mode set with `set_compare_mode`, default to PWM mode 1
+                        tim.$ccmrx_output().modify(|r, w| {
+                            let mode = match r.$ocxm().bits() {
+                                0 => OutputCompareMode::PwmMode1 as u8,
+                                mode => mode,
+                            };
+                            w.$ocxpe().set_bit().$ocxm().bits(mode)
+                        });
                         tim.ccer().modify(|_, w| w.$ccxe().set_bit());
                         $(
                             tim.ccer().modify(|_, w| w.$ccxne().bit(true));
@@ -260,3 +551,19 @@ pwm! {
     TIM16: (tim16, arr),
     TIM17: (tim17, arr),
 }
+
+pwm_direction! {
+    TIM1,
+    TIM3,
+}
+
+pwm_sync! {
+    TIM1,
+    TIM3,
+}
+
+pwm_repetition! {
+    TIM1,
+    TIM16,
+    TIM17,
+}
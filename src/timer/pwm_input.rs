@@ -0,0 +1,117 @@
+//! PWM input mode
+//!
+//! TI1 is captured on both channel 1 (rising edge, period) and channel 2 (falling edge,
+//! duty), and the counter is reset on every rising edge.
+use core::cmp;
+
+use crate::rcc::*;
+use crate::time::Hertz;
+use crate::timer::pins::TimerPin;
+use crate::timer::*;
+
+pub struct PwmInput<TIM, PIN> {
+    tim: TIM,
+    pin: PIN,
+    clk: Hertz,
+}
+
+pub trait PwmInputExt<PIN>: Sized {
+    /// Measure the signal on channel 1, `min_freq` is the lowest frequency whose period
+    /// still fits in the 16 bit counter. Zero, or a frequency below what the prescaler
+    /// can reach, selects the slowest counter clock.
+    fn pwm_input(self, pin: PIN, min_freq: Hertz, rcc: &mut Rcc) -> PwmInput<Self, PIN>;
+}
+
+macro_rules! pwm_input {
+    ($($TIMX:ident: $timX:ident,)+) => {
+        $(
+            impl<PIN> PwmInputExt<PIN> for $TIMX where PIN: TimerPin<$TIMX, Channel1> {
+                fn pwm_input(self, pin: PIN, min_freq: Hertz, rcc: &mut Rcc) -> PwmInput<Self, PIN> {
+                    PwmInput::$timX(self, pin, min_freq, rcc)
+                }
+            }
+
+            impl<PIN> PwmInput<$TIMX, PIN> where PIN: TimerPin<$TIMX, Channel1> {
+                fn $timX(tim: $TIMX, pin: PIN, min_freq: Hertz, rcc: &mut Rcc) -> Self {
+                    $TIMX::enable(rcc);
+                    $TIMX::reset(rcc);
+
+                    let clk = rcc.clocks.apb_tim_clk;
+                    let psc = cmp::min(clk.raw() / cmp::max(min_freq.raw(), 1) / 0x1_0000, 0xffff);
+
+                    // CC1 and CC2 both capture TI1
+                    tim.ccmr1_input().write(|w| unsafe { w.cc1s().bits(0b01).cc2s().bits(0b10) });
+
+                    // CC1 on the rising edge, CC2 on the falling edge
+                    tim.ccer().write(|w| {
+                        w.cc1p()
+                            .clear_bit()
+                            .cc1np()
+                            .clear_bit()
+                            .cc2p()
+                            .set_bit()
+                            .cc2np()
+                            .clear_bit()
+                            .cc1e()
+                            .set_bit()
+                            .cc2e()
+                            .set_bit()
+                    });
+
+                    // Reset the counter on TI1FP1
+                    tim.smcr().write(|w| unsafe { w.ts1().bits(0b101).sms1().bits(0b100) });
+
+                    tim.psc().write(|w| unsafe { w.psc().bits(psc as u16) });
+                    tim.arr().write(|w| unsafe { w.bits(0xffff) });
+                    tim.egr().write(|w| w.ug().set_bit());
+
+                    TimerPin::<$TIMX, Channel1>::setup(&pin);
+
+                    tim.cr1().write(|w| w.cen().set_bit());
+                    let clk = Hertz::from_raw(clk.raw() / (psc + 1));
+                    PwmInput { tim, pin, clk }
+                }
+
+                /// Returns the signal period in counter ticks
+                pub fn get_period_ticks(&self) -> u32 {
+                    self.tim.ccr1().read().bits() as u32
+                }
+
+                /// Returns the high time in counter ticks
+                pub fn get_duty_ticks(&self) -> u32 {
+                    self.tim.ccr2().read().bits() as u32
+                }
+
+                /// Returns the counter tick rate
+                pub fn tick_freq(&self) -> Hertz {
+                    self.clk
+                }
+
+                /// Returns the measured frequency, zero before the first full period
+                pub fn get_frequency(&self) -> Hertz {
+                    match self.get_period_ticks() {
+                        0 => Hertz::from_raw(0),
+                        ticks => Hertz::from_raw(self.clk.raw() / ticks),
+                    }
+                }
+
+                /// Returns the measured duty cycle in percent
+                pub fn get_duty_percent(&self) -> u8 {
+                    match self.get_period_ticks() {
+                        0 => 0,
+                        ticks => (self.get_duty_ticks().min(ticks) as u64 * 100 / ticks as u64) as u8,
+                    }
+                }
+
+                pub fn release(self) -> ($TIMX, PIN) {
+                    (self.tim, TimerPin::<$TIMX, Channel1>::release(self.pin))
+                }
+            }
+        )+
+    }
+}
+
+pwm_input! {
+    TIM1: tim1,
+    TIM3: tim3,
+}
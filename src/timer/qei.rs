@@ -10,6 +10,20 @@ pub struct Qei<TIM, PINS> {
     pins: PINS,
 }
 
+/// Encoder input
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum QeiChannel {
+    Channel1,
+    Channel2,
+}
+
+/// Encoder input polarity, inverting one input reverses the counting direction
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Polarity {
+    Normal,
+    Inverted,
+}
+
 pub trait QeiPins<TIM> {
     fn setup(&self);
     fn release(self) -> Self;
@@ -17,16 +31,19 @@ pub trait QeiPins<TIM> {
 
 impl<TIM, P1, P2> QeiPins<TIM> for (P1, P2)
 where
-    P1: TimerPin<TIM, Channel = Channel1>,
-    P2: TimerPin<TIM, Channel = Channel2>,
+    P1: TimerPin<TIM, Channel1>,
+    P2: TimerPin<TIM, Channel2>,
 {
     fn setup(&self) {
-        self.0.setup();
-        self.1.setup();
+        TimerPin::<TIM, Channel1>::setup(&self.0);
+        TimerPin::<TIM, Channel2>::setup(&self.1);
     }
 
     fn release(self) -> Self {
-        (self.0.release(), self.1.release())
+        (
+            TimerPin::<TIM, Channel1>::release(self.0),
+            TimerPin::<TIM, Channel2>::release(self.1),
+        )
     }
 }
 
@@ -74,6 +91,31 @@ macro_rules! qei {
                     Qei { tim, pins }
                 }
 
+                /// Changes the input polarity while the encoder keeps counting
+                pub fn set_polarity(&mut self, channel: QeiChannel, polarity: Polarity) {
+                    let inverted = polarity == Polarity::Inverted;
+                    match channel {
+                        QeiChannel::Channel1 => self.tim.ccer().modify(|_, w| w.cc1p().bit(inverted)),
+                        QeiChannel::Channel2 => self.tim.ccer().modify(|_, w| w.cc2p().bit(inverted)),
+                    }
+                }
+
+                /// Enables capture on an encoder input
+                pub fn enable_channel(&mut self, channel: QeiChannel) {
+                    match channel {
+                        QeiChannel::Channel1 => self.tim.ccer().modify(|_, w| w.cc1e().set_bit()),
+                        QeiChannel::Channel2 => self.tim.ccer().modify(|_, w| w.cc2e().set_bit()),
+                    }
+                }
+
+                /// Disables capture on an encoder input
+                pub fn disable_channel(&mut self, channel: QeiChannel) {
+                    match channel {
+                        QeiChannel::Channel1 => self.tim.ccer().modify(|_, w| w.cc1e().clear_bit()),
+                        QeiChannel::Channel2 => self.tim.ccer().modify(|_, w| w.cc2e().clear_bit()),
+                    }
+                }
+
                 pub fn release(self) -> ($TIMX, PINS) {
                     (self.tim, self.pins.release())
                 }
@@ -101,15 +101,28 @@ impl WindowWatchdog {
         assert!(window <= 0x40);
         self.wwdg
             .cfr()
-            .write(|w| unsafe { w.wdgtb().bits(psc).w().bits(window as u8) });
+            .modify(|_, w| unsafe { w.wdgtb().bits(psc).w().bits(window as u8) });
     }
 
+    /// Enables the early wakeup interrupt, raised when the counter reaches 0x40
+    /// one tick before the reset. Feeding from the handler avoids the reset.
     pub fn listen(&mut self) {
-        self.wwdg.cfr().write(|w| w.ewi().set_bit());
+        self.wwdg.cfr().modify(|_, w| w.ewi().set_bit());
     }
 
+    /// Disables the early wakeup interrupt
     pub fn unlisten(&mut self) {
-        self.wwdg.cfr().write(|w| w.ewi().clear_bit());
+        self.wwdg.cfr().modify(|_, w| w.ewi().clear_bit());
+    }
+
+    /// Returns true if the early wakeup interrupt is pending
+    pub fn is_early_wakeup_pending(&self) -> bool {
+        self.wwdg.sr().read().ewif().bit_is_set()
+    }
+
+    /// Clears the early wakeup interrupt flag
+    pub fn clear_early_wakeup(&mut self) {
+        self.wwdg.sr().write(|w| w.ewif().clear_bit());
     }
 
     pub fn release(self) -> WWDG {